@@ -0,0 +1,87 @@
+use crate::{
+    middleware::RequestCounter,
+    service::{render, ExpositionFormat, SharedSnapshot},
+};
+use actix_web::{get, http::header, web::Data, HttpRequest, HttpResponse};
+
+pub fn init(ctx: &mut actix_web::web::ServiceConfig) {
+    ctx.service(metrics);
+}
+
+/// Who may scrape `/metrics`, set by METRICS_ACCESS.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum MetricsAccess {
+    // `off`, the endpoint answers 404
+    Disabled,
+    // `local`, loopback peers only
+    #[default]
+    Local,
+    // `public`, anyone
+    Public,
+    // `token:<secret>`, `Authorization: Bearer <secret>` required
+    Token(String),
+}
+
+impl MetricsAccess {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "off" => Some(MetricsAccess::Disabled),
+            "local" => Some(MetricsAccess::Local),
+            "public" => Some(MetricsAccess::Public),
+            v => match v.strip_prefix("token:") {
+                Some(token) if !token.is_empty() => Some(MetricsAccess::Token(token.to_string())),
+                _ => None,
+            },
+        }
+    }
+}
+
+// compare every byte so the time taken does not leak the prefix matched
+#[inline]
+fn same_token(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[get("/metrics")]
+async fn metrics(
+    req: HttpRequest,
+    access: Data<MetricsAccess>,
+    snapshot: Data<SharedSnapshot>,
+    requests: Data<RequestCounter>,
+) -> HttpResponse {
+    match access.get_ref() {
+        MetricsAccess::Disabled => return HttpResponse::NotFound().finish(),
+        MetricsAccess::Local => {
+            if !matches!(req.peer_addr(), Some(address) if address.ip().is_loopback()) {
+                return HttpResponse::Forbidden().body(r#"{"info":"metrics are local only"}"#);
+            }
+        }
+        MetricsAccess::Public => {}
+        MetricsAccess::Token(token) => {
+            let given = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "));
+            if !matches!(given, Some(given) if same_token(given.as_bytes(), token.as_bytes())) {
+                return HttpResponse::Unauthorized()
+                    .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                    .body(r#"{"info":"auth not passed"}"#);
+            }
+        }
+    }
+    let format = ExpositionFormat::negotiate(
+        req.headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let body = render(&snapshot.read(), &requests.counts(), format);
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(body)
+}
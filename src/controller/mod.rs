@@ -1,8 +1,10 @@
 mod file;
+mod metrics;
 mod os;
 mod r#static;
 mod verify;
 pub use file::init as file;
+pub use metrics::{init as metrics, MetricsAccess};
 pub use os::init as os;
 pub use r#static::init as r#static;
 pub use verify::init as verify;
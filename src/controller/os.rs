@@ -1,47 +1,416 @@
 use crate::{
-    service::Shell,
-    tools::{cpu_info::cpu_info, cpu_stat::cpu_stat, log, mem_info::mem_info, LogLevel},
+    dao::key_from_user_kind,
+    middleware::CodeGuard,
+    service::{
+        event_stream, MetricStream, SharedHistory, SharedSnapshot, Shell, Subscriptions, Topic,
+    },
+    tools::{
+        cpu_freq::set_governor,
+        crypto::{crypto, crypto_current},
+        log,
+        parse::ParseMode,
+        pid_detail::pid_detail,
+        sockets::sockets,
+        with_size_format, AccountFiles, LogLevel, ProcRoot, SizeFormat, SysRoot,
+    },
 };
 use actix_web::{
     get, post,
-    web::{self, Payload, Query},
-    Error, HttpRequest, HttpResponse, Responder,
+    web::{self, Data, Json, Payload, Query},
+    Error, HttpRequest, HttpResponse,
 };
 use actix_web_actors::ws;
+use chrono::Utc;
+use deadpool_postgres::Pool;
 use libc::{termios, winsize, NCCS};
 use libc_tools::{Pty, PtyError};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::io;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr},
+};
 pub fn init(ctx: &mut web::ServiceConfig) {
-    ctx.service(web::scope("/os").service(proc).service(shell));
+    ctx.service(
+        web::scope("/os")
+            .service(proc)
+            .service(process)
+            .service(governor)
+            .service(socket_table)
+            .service(history)
+            .service(metric_stream)
+            .service(events)
+            .service(shell),
+    );
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 enum OsInfoType {
     MemInfo,
+    Cgroups,
+    CpuFreq,
     CpuInfo,
     CpuStat,
+    CpuTopology,
+    CpuUsage,
+    DiskStats,
+    Filesystems,
+    Interrupts,
+    LoadAvg,
+    Memory,
+    NetDev,
+    ParseWarnings,
     PidInfo,
+    Pressure,
+    Sensors,
+    Summary,
+    VmStat,
 }
 
 #[derive(Deserialize, Serialize)]
 struct OsInfoQuery {
     path: OsInfoType,
+    // sizes as bytes (the default), iec or si strings
+    units: Option<SizeFormat>,
 }
 
-// will accept path like {baseurl}/os/proc?info={...OsInfoType}
+// will accept path like {baseurl}/os/proc?info={...OsInfoType}&units=iec
 #[post("/proc")]
-async fn proc(Query(OsInfoQuery { path }): Query<OsInfoQuery>) -> io::Result<impl Responder> {
-    let res = HttpResponse::Ok()
+async fn proc(
+    Query(OsInfoQuery { path, units }): Query<OsInfoQuery>,
+    snapshot: Data<SharedSnapshot>,
+) -> io::Result<HttpResponse> {
+    let snapshot = snapshot.read();
+    if snapshot.seq == 0 {
+        return Ok(HttpResponse::ServiceUnavailable().body(r#"{"info":"not sampled yet"}"#));
+    }
+    let units = units.unwrap_or_default();
+    let body = match path {
+        OsInfoType::Cgroups => json!({
+            "tree": with_size_format(&snapshot.cgroups, units),
+            "rates": with_size_format(&snapshot.cgroup_rates, units),
+        })
+        .to_string(),
+        OsInfoType::CpuFreq => json!(with_size_format(&snapshot.cpu_freq, units)).to_string(),
+        OsInfoType::CpuInfo => json!(with_size_format(&snapshot.cpu_info, units)).to_string(),
+        OsInfoType::CpuStat => json!(with_size_format(&snapshot.cpu_stat, units)).to_string(),
+        OsInfoType::CpuTopology => json!({
+            "topology": with_size_format(&snapshot.cpu_topology, units),
+            "nodes": with_size_format(&snapshot.numa_nodes, units),
+        })
+        .to_string(),
+        OsInfoType::CpuUsage => json!(with_size_format(&snapshot.cpu_usage, units)).to_string(),
+        OsInfoType::MemInfo => json!(with_size_format(&snapshot.mem_info, units)).to_string(),
+        OsInfoType::DiskStats => json!({
+            "devices": with_size_format(&snapshot.disk_stats, units),
+            "rates": with_size_format(&snapshot.disk_rates, units),
+        })
+        .to_string(),
+        OsInfoType::Filesystems => {
+            json!(with_size_format(&snapshot.filesystems, units)).to_string()
+        }
+        OsInfoType::Interrupts => json!({
+            "interrupts": with_size_format(&snapshot.interrupts, units),
+            "rates": with_size_format(&snapshot.interrupt_rates, units),
+        })
+        .to_string(),
+        OsInfoType::LoadAvg => json!(with_size_format(&snapshot.load_avg, units)).to_string(),
+        OsInfoType::Memory => json!(with_size_format(&snapshot.memory, units)).to_string(),
+        OsInfoType::NetDev => json!({
+            "interfaces": with_size_format(&snapshot.net_interfaces, units),
+            "rates": with_size_format(&snapshot.net_rates, units),
+        })
+        .to_string(),
+        OsInfoType::ParseWarnings => {
+            json!(with_size_format(&snapshot.parse_warnings, units)).to_string()
+        }
+        OsInfoType::PidInfo => json!(with_size_format(&snapshot.processes, units)).to_string(),
+        OsInfoType::Pressure => json!({
+            "system": with_size_format(&snapshot.pressure, units),
+            "cgroups": with_size_format(&snapshot.cgroup_pressure, units),
+        })
+        .to_string(),
+        OsInfoType::Sensors => json!(with_size_format(&snapshot.sensors, units)).to_string(),
+        OsInfoType::Summary => json!(with_size_format(&snapshot.summary, units)).to_string(),
+        OsInfoType::VmStat => json!({
+            "counters": with_size_format(&snapshot.vm_stat, units),
+            "rates": with_size_format(&snapshot.vm_rate, units),
+        })
+        .to_string(),
+    };
+    Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body(match path {
-            OsInfoType::CpuInfo => json!(cpu_info()?).to_string(),
-            OsInfoType::CpuStat => json!(cpu_stat()?).to_string(),
-            OsInfoType::MemInfo => json!(mem_info()?).to_string(),
-            OsInfoType::PidInfo => json!({}).to_string(),
-        });
-    Ok(res)
+        .body(body))
+}
+
+// None when `code` is the current code of the Owner, else what to answer
+async fn owner_code(
+    req: &HttpRequest,
+    guard: &CodeGuard,
+    client: &Pool,
+    code: Option<u32>,
+) -> Result<Option<HttpResponse>, Box<dyn std::error::Error>> {
+    let address = req
+        .peer_addr()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip());
+    if guard.locked_out(address) {
+        return Ok(Some(
+            HttpResponse::TooManyRequests().body(r#"{"info":"too many attempts"}"#),
+        ));
+    }
+    let key = key_from_user_kind(&client.get().await?, "Owner").await?;
+    let step = crypto_current();
+    match code {
+        Some(code) if guard.verify(address, code, crypto(step, key.as_bytes()), step) => Ok(None),
+        _ => Ok(Some(
+            HttpResponse::Forbidden().body(r#"{"info":"auth not passed"}"#),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct ProcessQuery {
+    environ: Option<bool>,
+    units: Option<SizeFormat>,
+    // current code of the Owner, only needed with environ=true
+    code: Option<u32>,
+}
+
+// will accept path like {baseurl}/os/proc/{pid}?environ=true&code=123456&units=iec
+#[post("/proc/{pid}")]
+async fn process(
+    req: HttpRequest,
+    pid: web::Path<u32>,
+    Query(ProcessQuery {
+        environ,
+        units,
+        code,
+    }): Query<ProcessQuery>,
+    proc_root: Data<ProcRoot>,
+    accounts: Data<AccountFiles>,
+    client: Data<Pool>,
+    guard: Data<CodeGuard>,
+) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+    // the environment holds credentials more often than not
+    if environ == Some(true) {
+        if let Some(denied) = owner_code(&req, &guard, &client, code).await? {
+            return Ok(denied);
+        }
+    }
+    let pid = pid.into_inner();
+    let (proc_root, accounts) = (proc_root.get_ref().clone(), accounts.get_ref().clone());
+    let detail =
+        web::block(move || pid_detail(&proc_root, &accounts, pid, environ.unwrap_or(false)))
+            .await?;
+    match detail {
+        Ok(detail) => Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!(with_size_format(&detail, units.unwrap_or_default())).to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Ok(HttpResponse::NotFound().body(r#"{"info":"process not found"}"#))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct SocketQuery {
+    port: Option<u16>,
+    // e.g. LISTEN, see `Socket::state`
+    state: Option<String>,
+    // tcp, tcp6, udp, udp6 or unix
+    protocol: Option<String>,
+}
+
+// read on demand, walking the fds of every process is too slow to sample
+// will accept path like {baseurl}/os/sockets?port=8080&state=LISTEN&protocol=tcp
+// no units, a socket holds no sizes
+#[post("/sockets")]
+async fn socket_table(
+    Query(SocketQuery {
+        port,
+        state,
+        protocol,
+    }): Query<SocketQuery>,
+    proc_root: Data<ProcRoot>,
+) -> Result<HttpResponse, Error> {
+    let proc_root = proc_root.get_ref().clone();
+    let mut parsed = web::block(move || sockets(&proc_root, ParseMode::Lenient)).await??;
+    parsed.data.retain(|socket| {
+        (port.is_none() || socket.local_port == port)
+            && (state.is_none() || socket.state == state)
+            && (protocol.is_none() || protocol.as_ref() == Some(&socket.protocol))
+    });
+    Ok(HttpResponse::Ok().content_type("application/json").body(
+        json!({
+            "sockets": parsed.data,
+            "warnings": parsed.warnings,
+        })
+        .to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct Governor {
+    governor: String,
+    // current code of the Owner, see verify/check
+    code: u32,
+}
+
+// will accept path like {baseurl}/os/cpufreq/{policy}/governor
+// with {"governor":"performance","code":123456}, Owner only, each code once
+#[post("/cpufreq/{policy}/governor")]
+async fn governor(
+    req: HttpRequest,
+    policy: web::Path<u32>,
+    data: Json<Governor>,
+    client: Data<Pool>,
+    sys_root: Data<SysRoot>,
+    guard: Data<CodeGuard>,
+) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+    if let Some(denied) = owner_code(&req, &guard, &client, Some(data.code)).await? {
+        return Ok(denied);
+    }
+    let (policy, sys_root) = (policy.into_inner(), sys_root.get_ref().clone());
+    let Governor { governor, .. } = data.into_inner();
+    let changed = governor.clone();
+    let result = web::block(move || set_governor(&sys_root, policy, changed.as_str())).await?;
+    match result {
+        Ok(()) => {
+            log(LogLevel::Info(
+                std::format!("cpufreq policy{} governor set to {}", policy, governor).as_str(),
+            ));
+            Ok(HttpResponse::Ok().body(r#"{"info":"ok"}"#))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Ok(HttpResponse::NotFound().body(r#"{"info":"policy not found"}"#))
+        }
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            Ok(HttpResponse::BadRequest().body(r#"{"info":"governor not available"}"#))
+        }
+        // serverd runs without root, or sysfs is mounted read-only
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            Ok(HttpResponse::Forbidden().body(r#"{"info":"no write access to sysfs"}"#))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    metric: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    step: Option<i64>,
+}
+
+// will accept path like {baseurl}/os/history?metric=cpu.busy&from={ms}&to={ms}&step={ms}
+// without metric it lists the recorded metric names
+#[post("/history")]
+async fn history(
+    Query(HistoryQuery {
+        metric,
+        from,
+        to,
+        step,
+    }): Query<HistoryQuery>,
+    history: Data<SharedHistory>,
+) -> HttpResponse {
+    let history = history.read();
+    let metric = match metric {
+        Some(m) => m,
+        None => {
+            return HttpResponse::Ok()
+                .content_type("application/json")
+                .body(json!(history.metrics()).to_string())
+        }
+    };
+    let to = to.unwrap_or_else(|| Utc::now().timestamp_millis());
+    let from = from.unwrap_or(to - 3_600_000);
+    if from > to {
+        return HttpResponse::BadRequest().body(r#"{"info":"from is after to"}"#);
+    }
+    match history.query(metric.as_str(), from, to, step) {
+        Some((step, points)) => HttpResponse::Ok().content_type("application/json").body(
+            json!({
+                "metric": metric,
+                "step": step,
+                "points": points,
+            })
+            .to_string(),
+        ),
+        None => HttpResponse::NotFound().body(r#"{"info":"metric not found"}"#),
+    }
+}
+
+// websocket, see `MetricStream` for the protocol
+#[get("/stream")]
+async fn metric_stream(
+    req: HttpRequest,
+    stream: Payload,
+    snapshot: Data<SharedSnapshot>,
+) -> Result<HttpResponse, Error> {
+    ws::start(MetricStream::new(snapshot.get_ref().clone()), &req, stream)
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    // comma separated topics, e.g. cpu,mem
+    topics: String,
+    interval: Option<u64>,
+    delta: Option<bool>,
+    units: Option<SizeFormat>,
+}
+
+// server-sent events, for clients that can not keep a websocket
+// will accept path like {baseurl}/os/events?topics=cpu,mem&interval={ms}&delta=true&units=iec
+// `units` applies to topic events, history points stay numbers as in /os/history
+#[get("/events")]
+async fn events(
+    req: HttpRequest,
+    Query(EventsQuery {
+        topics,
+        interval,
+        delta,
+        units,
+    }): Query<EventsQuery>,
+    snapshot: Data<SharedSnapshot>,
+    shared_history: Data<SharedHistory>,
+) -> HttpResponse {
+    let topics = match topics
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(Topic::parse)
+        .collect::<Option<Vec<Topic>>>()
+    {
+        Some(topics) if !topics.is_empty() => topics,
+        _ => return HttpResponse::BadRequest().body(r#"{"info":"unknown or missing topics"}"#),
+    };
+    let mut subscriptions = Subscriptions::default();
+    for topic in topics {
+        subscriptions.subscribe(
+            topic,
+            interval,
+            delta.unwrap_or(false),
+            units.unwrap_or_default(),
+        );
+    }
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i64>().ok());
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // nginx would otherwise hold events back
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(event_stream(
+            snapshot.get_ref(),
+            shared_history.get_ref(),
+            subscriptions,
+            last_event_id,
+        ))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,270 @@
+use std::error::Error;
+
+use deadpool_postgres::Pool;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+pub type DaoResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// One row of the `metrics` table: a raw sample when `step` is 0, otherwise
+/// the rollup of `[time, time + step)`.  Times and steps in milliseconds.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct MetricRow {
+    pub metric: String,
+    pub step: i64,
+    pub time: i64,
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub count: i64,
+}
+
+impl MetricRow {
+    pub fn sample(metric: &str, time: i64, value: f64) -> Self {
+        MetricRow {
+            metric: metric.to_string(),
+            step: 0,
+            time,
+            min: value,
+            avg: value,
+            max: value,
+            count: 1,
+        }
+    }
+}
+
+/// Where sampled metrics are kept across restarts.  Writing a row or a
+/// rollup bucket that already exists replaces it, so every job may be
+/// retried.
+pub trait MetricsRepository: Send + Sync {
+    fn migrate(&self) -> BoxFuture<'_, DaoResult<()>>;
+    fn insert<'a>(&'a self, rows: &'a [MetricRow]) -> BoxFuture<'a, DaoResult<u64>>;
+    /// Aggregate rows of `source` with `from <= time < to` into buckets of
+    /// `step`, returns the number of buckets written.
+    fn rollup(&self, source: i64, step: i64, from: i64, to: i64) -> BoxFuture<'_, DaoResult<u64>>;
+    /// Delete rows of `step` older than `before`.
+    fn expire(&self, step: i64, before: i64) -> BoxFuture<'_, DaoResult<u64>>;
+    /// Rows of `step` with `from <= time`, oldest first.
+    fn load(&self, step: i64, from: i64) -> BoxFuture<'_, DaoResult<Vec<MetricRow>>>;
+}
+
+static CREATE_METRICS: &str = "CREATE TABLE IF NOT EXISTS metrics (
+    metric TEXT NOT NULL,
+    step BIGINT NOT NULL,
+    time BIGINT NOT NULL,
+    min DOUBLE PRECISION NOT NULL,
+    avg DOUBLE PRECISION NOT NULL,
+    max DOUBLE PRECISION NOT NULL,
+    count BIGINT NOT NULL,
+    PRIMARY KEY (metric, step, time)
+)";
+
+// one statement per batch, the rows travel as arrays
+static INSERT_METRICS: &str = "INSERT INTO metrics (metric, step, time, min, avg, max, count)
+    SELECT * FROM UNNEST($1::TEXT[], $2::BIGINT[], $3::BIGINT[],
+        $4::DOUBLE PRECISION[], $5::DOUBLE PRECISION[], $6::DOUBLE PRECISION[], $7::BIGINT[])
+    ON CONFLICT (metric, step, time) DO UPDATE SET
+        min = EXCLUDED.min, avg = EXCLUDED.avg, max = EXCLUDED.max, count = EXCLUDED.count";
+
+static ROLLUP_METRICS: &str = "INSERT INTO metrics (metric, step, time, min, avg, max, count)
+    SELECT metric, $2::BIGINT, time - mod(time, $2::BIGINT), min(min),
+        sum(avg * count) / sum(count), max(max), sum(count)::BIGINT
+    FROM metrics WHERE step = $1::BIGINT AND time >= $3::BIGINT AND time < $4::BIGINT
+    GROUP BY metric, time - mod(time, $2::BIGINT)
+    ON CONFLICT (metric, step, time) DO UPDATE SET
+        min = EXCLUDED.min, avg = EXCLUDED.avg, max = EXCLUDED.max, count = EXCLUDED.count";
+
+pub struct PgMetricsRepository {
+    pool: Pool,
+}
+
+impl PgMetricsRepository {
+    pub fn new(pool: Pool) -> Self {
+        PgMetricsRepository { pool }
+    }
+}
+
+impl MetricsRepository for PgMetricsRepository {
+    fn migrate(&self) -> BoxFuture<'_, DaoResult<()>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            client.batch_execute(CREATE_METRICS).await?;
+            Ok(())
+        })
+    }
+
+    fn insert<'a>(&'a self, rows: &'a [MetricRow]) -> BoxFuture<'a, DaoResult<u64>> {
+        Box::pin(async move {
+            if rows.is_empty() {
+                return Ok(0);
+            }
+            let mut columns = (
+                Vec::with_capacity(rows.len()),
+                Vec::with_capacity(rows.len()),
+                Vec::with_capacity(rows.len()),
+                Vec::with_capacity(rows.len()),
+                Vec::with_capacity(rows.len()),
+                Vec::with_capacity(rows.len()),
+                Vec::with_capacity(rows.len()),
+            );
+            for row in rows {
+                columns.0.push(row.metric.as_str());
+                columns.1.push(row.step);
+                columns.2.push(row.time);
+                columns.3.push(row.min);
+                columns.4.push(row.avg);
+                columns.5.push(row.max);
+                columns.6.push(row.count);
+            }
+            let client = self.pool.get().await?;
+            Ok(client
+                .execute(
+                    INSERT_METRICS,
+                    &[
+                        &columns.0, &columns.1, &columns.2, &columns.3, &columns.4, &columns.5,
+                        &columns.6,
+                    ],
+                )
+                .await?)
+        })
+    }
+
+    fn rollup(&self, source: i64, step: i64, from: i64, to: i64) -> BoxFuture<'_, DaoResult<u64>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            Ok(client
+                .execute(ROLLUP_METRICS, &[&source, &step, &from, &to])
+                .await?)
+        })
+    }
+
+    fn expire(&self, step: i64, before: i64) -> BoxFuture<'_, DaoResult<u64>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            Ok(client
+                .execute(
+                    "DELETE FROM metrics WHERE step = $1::BIGINT AND time < $2::BIGINT",
+                    &[&step, &before],
+                )
+                .await?)
+        })
+    }
+
+    fn load(&self, step: i64, from: i64) -> BoxFuture<'_, DaoResult<Vec<MetricRow>>> {
+        Box::pin(async move {
+            let client = self.pool.get().await?;
+            let rows = client
+                .query(
+                    "SELECT metric, step, time, min, avg, max, count FROM metrics
+                    WHERE step = $1::BIGINT AND time >= $2::BIGINT ORDER BY time",
+                    &[&step, &from],
+                )
+                .await?;
+            let mut metrics = Vec::with_capacity(rows.len());
+            for row in rows {
+                metrics.push(MetricRow {
+                    metric: row.try_get(0)?,
+                    step: row.try_get(1)?,
+                    time: row.try_get(2)?,
+                    min: row.try_get(3)?,
+                    avg: row.try_get(4)?,
+                    max: row.try_get(5)?,
+                    count: row.try_get(6)?,
+                });
+            }
+            Ok(metrics)
+        })
+    }
+}
+
+/// Keeps the table in a map, what tests run the jobs against.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryMetricsRepository(
+    std::sync::Mutex<std::collections::BTreeMap<(i64, String, i64), MetricRow>>,
+);
+
+#[cfg(test)]
+impl MemoryMetricsRepository {
+    pub fn rows(&self, step: i64) -> Vec<MetricRow> {
+        let mut rows = self
+            .0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|row| row.step == step)
+            .cloned()
+            .collect::<Vec<MetricRow>>();
+        rows.sort_by_key(|row| row.time);
+        rows
+    }
+}
+
+#[cfg(test)]
+impl MetricsRepository for MemoryMetricsRepository {
+    fn migrate(&self) -> BoxFuture<'_, DaoResult<()>> {
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn insert<'a>(&'a self, rows: &'a [MetricRow]) -> BoxFuture<'a, DaoResult<u64>> {
+        Box::pin(async move {
+            let mut table = self.0.lock().unwrap();
+            let count = rows.len() as u64;
+            for row in rows {
+                table.insert((row.step, row.metric.clone(), row.time), row.clone());
+            }
+            Ok(count)
+        })
+    }
+
+    fn rollup(&self, source: i64, step: i64, from: i64, to: i64) -> BoxFuture<'_, DaoResult<u64>> {
+        Box::pin(async move {
+            let mut buckets = std::collections::BTreeMap::<(String, i64), MetricRow>::new();
+            for row in self
+                .rows(source)
+                .into_iter()
+                .filter(|row| row.time >= from && row.time < to)
+            {
+                let time = row.time - row.time.rem_euclid(step);
+                let bucket = buckets
+                    .entry((row.metric.clone(), time))
+                    .or_insert(MetricRow {
+                        metric: row.metric.clone(),
+                        step,
+                        time,
+                        min: f64::MAX,
+                        avg: 0.0,
+                        max: f64::MIN,
+                        count: 0,
+                    });
+                let count = bucket.count + row.count;
+                bucket.min = bucket.min.min(row.min);
+                bucket.max = bucket.max.max(row.max);
+                bucket.avg =
+                    (bucket.avg * bucket.count as f64 + row.avg * row.count as f64) / count as f64;
+                bucket.count = count;
+            }
+            let rows = buckets.into_values().collect::<Vec<MetricRow>>();
+            self.insert(&rows).await
+        })
+    }
+
+    fn expire(&self, step: i64, before: i64) -> BoxFuture<'_, DaoResult<u64>> {
+        Box::pin(async move {
+            let mut table = self.0.lock().unwrap();
+            let count = table.len();
+            table.retain(|_, row| row.step != step || row.time >= before);
+            Ok((count - table.len()) as u64)
+        })
+    }
+
+    fn load(&self, step: i64, from: i64) -> BoxFuture<'_, DaoResult<Vec<MetricRow>>> {
+        Box::pin(async move {
+            Ok(self
+                .rows(step)
+                .into_iter()
+                .filter(|row| row.time >= from)
+                .collect())
+        })
+    }
+}
@@ -1,2 +1,4 @@
+mod metrics;
 mod verify_code;
+pub use metrics::*;
 pub use verify_code::*;
@@ -1,8 +1,7 @@
 mod bean;
 pub mod controller;
-mod dao;
-mod middleware;
+pub mod dao;
+pub mod middleware;
 pub mod model;
-mod service;
+pub mod service;
 pub mod tools;
-
@@ -1,14 +1,23 @@
+use actix::{Actor, Arbiter};
 use actix_web::{dev::Service, web::Data, App, HttpServer};
 
+use chrono::Utc;
 use serverd::{
-    controller,
-    tools::{log, LogLevel},
+    controller::{self, MetricsAccess},
+    dao::{MetricsRepository, PgMetricsRepository},
+    middleware::{CodeGuard, RequestCounter},
+    service::{
+        restore_history, MetricsRecorder, Retention, Sampler, SharedHistory, SharedSnapshot,
+    },
+    tools::{log, AccountFiles, LogLevel, ProcRoot, SysRoot},
 };
 use tokio_postgres::NoTls;
 
 use std::{
     io,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
 };
 
 fn config() -> (&'static str, u16) {
@@ -30,6 +39,57 @@ fn config() -> (&'static str, u16) {
     address_port
 }
 
+// SAMPLE_INTERVAL in milliseconds, how often /proc is read
+fn sample_interval() -> Duration {
+    let millis = std::env::var("SAMPLE_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(millis.max(100))
+}
+
+// METRICS_RETENTION as `step=keep` pairs in seconds, see `Retention::parse`
+fn retention() -> Retention {
+    match std::env::var("METRICS_RETENTION") {
+        Ok(value) => Retention::parse(value.as_str()).unwrap_or_else(|| {
+            log(LogLevel::Warn(
+                std::format!("invalid METRICS_RETENTION {:?}, using default", value).as_str(),
+            ));
+            Retention::default()
+        }),
+        Err(_) => Retention::default(),
+    }
+}
+
+// METRICS_ACCESS is off, local, public or token:<secret>
+fn metrics_access() -> MetricsAccess {
+    match std::env::var("METRICS_ACCESS") {
+        Ok(value) => MetricsAccess::parse(value.as_str()).unwrap_or_else(|| {
+            log(LogLevel::Warn(
+                "invalid METRICS_ACCESS, /metrics is disabled",
+            ));
+            MetricsAccess::Disabled
+        }),
+        Err(_) => MetricsAccess::default(),
+    }
+}
+
+// PROC_ROOT and SYS_ROOT, where procfs and sysfs to watch are mounted, e.g.
+// the host's ones bind-mounted into a container, and PASSWD_FILE and
+// UTMP_FILE, the account files of that same host
+fn roots() -> (ProcRoot, SysRoot, AccountFiles) {
+    let proc = std::env::var("PROC_ROOT").map_or_else(|_| ProcRoot::default(), ProcRoot::new);
+    let sys = std::env::var("SYS_ROOT").map_or_else(|_| SysRoot::default(), SysRoot::new);
+    let mut accounts = AccountFiles::default();
+    if let Ok(passwd) = std::env::var("PASSWD_FILE") {
+        accounts.passwd = passwd.into();
+    }
+    if let Ok(utmp) = std::env::var("UTMP_FILE") {
+        accounts.utmp = utmp.into();
+    }
+    (proc, sys, accounts)
+}
+
 // use MVC model to build
 // use easiest to run it at first
 #[actix_web::main]
@@ -43,9 +103,64 @@ async fn main() -> io::Result<()> {
     config.dbname("postgres");
     let manager = deadpool_postgres::Manager::new(config, NoTls);
     let pool = deadpool_postgres::Pool::new(manager, 12);
+    let snapshot = SharedSnapshot::default();
+    let history = SharedHistory::default();
+    let interval = sample_interval();
+    let (proc, sys, accounts) = roots();
+    let retention = retention();
+    // history is refilled before sampling starts, so restored buckets stay
+    // older than the sampled ones
+    let repository: Arc<dyn MetricsRepository> = Arc::new(PgMetricsRepository::new(pool.clone()));
+    let recorder = match repository.migrate().await {
+        Ok(()) => {
+            let now = Utc::now().timestamp_millis();
+            match restore_history(
+                &*repository,
+                &retention,
+                &history,
+                interval.as_millis() as i64,
+                now,
+            )
+            .await
+            {
+                Ok(rows) => log(LogLevel::Info(
+                    std::format!("restored {} metric rows", rows).as_str(),
+                )),
+                Err(e) => log(LogLevel::Warn(
+                    std::format!("restore metrics history failed: {}", e).as_str(),
+                )),
+            }
+            Some(MetricsRecorder::new(repository, retention).start())
+        }
+        Err(e) => {
+            log(LogLevel::Warn(
+                std::format!("metrics are not persisted: {}", e).as_str(),
+            ));
+            None
+        }
+    };
+    // keep blocking /proc reads away from the http workers
+    let sampler_arbiter = Arbiter::new();
+    let (sampler_snapshot, sampler_history) = (snapshot.clone(), history.clone());
+    let sampler_roots = (proc.clone(), sys.clone(), accounts.clone());
+    Sampler::start_in_arbiter(&sampler_arbiter.handle(), move |_| {
+        let sampler = Sampler::new(interval, sampler_snapshot, sampler_history).read_from(
+            sampler_roots.0,
+            sampler_roots.1,
+            sampler_roots.2,
+        );
+        match recorder {
+            Some(recorder) => sampler.record_to(recorder.recipient()),
+            None => sampler,
+        }
+    });
+    let metrics_access = metrics_access();
+    let requests = RequestCounter::default();
+    let code_guard = CodeGuard::default();
     HttpServer::new(move || {
+        let counter = requests.clone();
         App::new()
-            .wrap_fn(|req, service| {
+            .wrap_fn(move |req, service| {
                 let path = req.path();
                 let address = req.peer_addr().unwrap_or(SocketAddr::V4(SocketAddrV4::new(
                     Ipv4Addr::new(0, 0, 0, 0),
@@ -71,9 +186,30 @@ async fn main() -> io::Result<()> {
                     )
                     .as_str(),
                 ));
-                service.call(req)
+                // counted by route pattern, so ids in paths stay one series
+                let (counter, method, pattern) =
+                    (counter.clone(), method.to_string(), req.match_pattern());
+                let response = service.call(req);
+                async move {
+                    let response = response.await;
+                    let status = match &response {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    counter.count(method.as_str(), pattern.as_deref(), status.as_u16());
+                    response
+                }
             })
             .app_data(Data::new(pool.clone()))
+            .app_data(Data::new(snapshot.clone()))
+            .app_data(Data::new(history.clone()))
+            .app_data(Data::new(requests.clone()))
+            .app_data(Data::new(code_guard.clone()))
+            .app_data(Data::new(metrics_access.clone()))
+            .app_data(Data::new(proc.clone()))
+            .app_data(Data::new(sys.clone()))
+            .app_data(Data::new(accounts.clone()))
+            .configure(controller::metrics)
             .configure(controller::os)
             .configure(controller::r#static)
             .configure(controller::verify)
@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+// wrong codes a client may send per LOCKOUT
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct Attempts {
+    // per client address, the start of its window and the failures in it
+    failures: HashMap<IpAddr, (Instant, u32)>,
+    // time step of the last code accepted
+    last_step: Option<u64>,
+}
+
+/// Guards the one-time codes of endpoints that change the machine, such
+/// as the cpufreq governor.  A client is locked out after MAX_FAILURES
+/// wrong codes, and a code is accepted once only, as RFC 6238 asks, so
+/// one seen on the wire cannot be replayed within its 30 seconds.
+#[derive(Clone, Default)]
+pub struct CodeGuard(Arc<Mutex<Attempts>>);
+
+impl CodeGuard {
+    fn attempts(&self) -> MutexGuard<'_, Attempts> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn locked_out(&self, client: IpAddr) -> bool {
+        let mut attempts = self.attempts();
+        // windows that are over are forgotten, or the map only grows
+        attempts
+            .failures
+            .retain(|_, (start, _)| start.elapsed() < LOCKOUT);
+        matches!(attempts.failures.get(&client), Some((_, n)) if *n >= MAX_FAILURES)
+    }
+
+    /// Whether `code` is `expected`, the code of time step `step`, and no
+    /// code of that step or a later one was accepted before.  Anything
+    /// else counts as a failure of `client`.
+    pub fn verify(&self, client: IpAddr, code: u32, expected: u32, step: u64) -> bool {
+        let mut attempts = self.attempts();
+        if code == expected && attempts.last_step.is_none_or(|last| step > last) {
+            attempts.last_step = Some(step);
+            attempts.failures.remove(&client);
+            return true;
+        }
+        let failures = attempts
+            .failures
+            .entry(client)
+            .or_insert_with(|| (Instant::now(), 0));
+        if failures.0.elapsed() >= LOCKOUT {
+            *failures = (Instant::now(), 0);
+        }
+        failures.1 += 1;
+        false
+    }
+}
+
+#[test]
+fn code_guard_test() {
+    let guard = CodeGuard::default();
+    let (client, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+    assert!(guard.verify(client, 123_456, 123_456, 100));
+    // replayed within its step
+    assert!(!guard.verify(other, 123_456, 123_456, 100));
+    assert!(guard.verify(other, 654_321, 654_321, 101));
+    for _ in 0..MAX_FAILURES {
+        assert!(!guard.locked_out(client));
+        assert!(!guard.verify(client, 1, 2, 102));
+    }
+    assert!(guard.locked_out(client));
+    assert!(!guard.locked_out(other));
+}
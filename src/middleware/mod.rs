@@ -1 +1,5 @@
+mod code_guard;
 mod cors;
+mod requests;
+pub use code_guard::*;
+pub use requests::*;
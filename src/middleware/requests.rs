@@ -0,0 +1,37 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// (method, route pattern, status code)
+pub type RequestKey = (String, String, u16);
+
+/// Requests served since start, counted by the logging `wrap_fn` in main.
+/// Routes are counted by their pattern so `/os/proc/{pid}` stays one series.
+#[derive(Clone, Default)]
+pub struct RequestCounter(Arc<Mutex<BTreeMap<RequestKey, u64>>>);
+
+impl RequestCounter {
+    pub fn count(&self, method: &str, pattern: Option<&str>, status: u16) {
+        let key = (
+            method.to_string(),
+            pattern.unwrap_or("unmatched").to_string(),
+            status,
+        );
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(key)
+            .or_insert(0) += 1;
+    }
+
+    pub fn counts(&self) -> Vec<(RequestKey, u64)> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(key, count)| (key.clone(), *count))
+            .collect()
+    }
+}
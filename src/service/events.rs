@@ -0,0 +1,142 @@
+use std::{convert::Infallible, time::Duration};
+
+use actix_web::web::Bytes;
+use futures_util::{future, stream, stream::BoxStream, StreamExt};
+use serde_json::json;
+use tokio::time::{interval_at, Instant};
+
+use crate::service::{replay, updates, SharedHistory, SharedSnapshot, Subscriptions, Update};
+
+// how long an EventSource waits before reconnecting, in milliseconds
+const RETRY: u64 = 3000;
+// proxies close connections that stay silent for too long
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// One server-sent event, `data` must be a single line such as JSON.
+fn sse_event(id: Option<i64>, event: &str, data: &str) -> String {
+    match id {
+        Some(id) => format!("id: {}\nevent: {}\ndata: {}\n\n", id, event, data),
+        None => format!("event: {}\ndata: {}\n\n", event, data),
+    }
+}
+
+// live events of one snapshot, named after their topic
+fn topic_events(subscriptions: &mut Subscriptions, update: Update) -> String {
+    match update {
+        Update::Snapshot(snapshot) => subscriptions
+            .events(&snapshot)
+            .into_iter()
+            .map(|event| {
+                let name = json!(event.topic);
+                sse_event(
+                    Some(event.time),
+                    name.as_str().unwrap_or_default(),
+                    json!(event).to_string().as_str(),
+                )
+            })
+            .collect(),
+        Update::Lagged(skipped) => sse_event(
+            None,
+            "lagged",
+            json!({ "skipped": skipped }).to_string().as_str(),
+        ),
+    }
+}
+
+enum Tick {
+    Update(Update),
+    KeepAlive,
+}
+
+/// The body of an `text/event-stream` response.  It starts with the
+/// `retry` hint, then what was missed since `last_event_id` as `history`
+/// events, then the latest snapshot, and goes on with every snapshot the
+/// sampler publishes.  Event ids are snapshot times, so a reconnecting
+/// client resumes where it stopped.
+pub fn event_stream(
+    snapshot: &SharedSnapshot,
+    history: &SharedHistory,
+    mut subscriptions: Subscriptions,
+    last_event_id: Option<i64>,
+) -> BoxStream<'static, Result<Bytes, Infallible>> {
+    let mut preamble = format!("retry: {}\n\n", RETRY);
+    if let Some(time) = last_event_id {
+        let missed = replay(&history.read(), &subscriptions.topics(), time);
+        for (topic, metric, points) in missed {
+            let last = points.last().map(|p| p.time);
+            let data = json!({ "topic": topic, "metric": metric, "points": points });
+            preamble.push_str(sse_event(last, "history", data.to_string().as_str()).as_str());
+        }
+    }
+    /*
+       Subscribe before reading the latest snapshot, so a sample published
+       in between is not lost.  It may then come twice, and `events` drops
+       the second as not due yet.
+    */
+    let published = updates(snapshot);
+    let latest = snapshot.read().clone();
+    if latest.seq != 0 {
+        preamble.push_str(topic_events(&mut subscriptions, Update::Snapshot(latest)).as_str());
+    }
+    let keepalive = stream::unfold(
+        interval_at(Instant::now() + KEEPALIVE, KEEPALIVE),
+        |mut interval| async move {
+            interval.tick().await;
+            Some((Tick::KeepAlive, interval))
+        },
+    );
+    let live = stream::select(published.map(Tick::Update), keepalive).filter_map(move |tick| {
+        let text = match tick {
+            Tick::Update(update) => topic_events(&mut subscriptions, update),
+            Tick::KeepAlive => ": keepalive\n\n".to_string(),
+        };
+        future::ready(if text.is_empty() {
+            None
+        } else {
+            Some(Ok(Bytes::from(text)))
+        })
+    });
+    stream::once(future::ready(Ok(Bytes::from(preamble))))
+        .chain(live)
+        .boxed()
+}
+
+#[tokio::test]
+async fn event_stream_test() {
+    use crate::{
+        service::{History, Topic},
+        tools::SizeFormat,
+    };
+    assert_eq!(
+        sse_event(Some(42), "cpu", r#"{"a":1}"#),
+        "id: 42\nevent: cpu\ndata: {\"a\":1}\n\n"
+    );
+    let history = SharedHistory::new(History::new(10, vec![]));
+    {
+        let mut history = history.write();
+        for second in 1..=5i64 {
+            history.record("cpu.busy", second * 1000, second as f64);
+            history.record("swap.used", second * 1000, 0.0);
+            history.record("processes", second * 1000, 100.0);
+        }
+    }
+    let mut subscriptions = Subscriptions::default();
+    subscriptions.subscribe(Topic::Cpu, None, false, SizeFormat::Bytes);
+    subscriptions.subscribe(Topic::Mem, None, false, SizeFormat::Bytes);
+    let mut events = event_stream(
+        &SharedSnapshot::default(),
+        &history,
+        subscriptions,
+        Some(3000),
+    );
+    let preamble = events.next().await.unwrap().unwrap();
+    let preamble = String::from_utf8(preamble.to_vec()).unwrap();
+    assert!(preamble.starts_with("retry: 3000\n\n"));
+    // cpu.busy and swap.used after 3s, nothing about processes
+    assert_eq!(preamble.matches("event: history\n").count(), 2);
+    assert!(preamble.contains("id: 5000\nevent: history\n"));
+    assert!(preamble.contains(
+        r#""metric":"cpu.busy","points":[{"time":4000,"value":4.0},{"time":5000,"value":5.0}]"#
+    ));
+    assert!(!preamble.contains("processes"));
+}
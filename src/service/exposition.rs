@@ -0,0 +1,353 @@
+use std::fmt::Write;
+
+use crate::{
+    middleware::RequestKey,
+    service::Snapshot,
+    tools::{cpu_stat::CoreStat, cpu_usage::clock_ticks, BitType},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpositionFormat {
+    // text format 0.0.4
+    Prometheus,
+    OpenMetrics,
+}
+
+impl ExpositionFormat {
+    /// OpenMetrics only when the scraper asks for it.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        match accept {
+            Some(accept) if accept.contains("application/openmetrics-text") => {
+                ExpositionFormat::OpenMetrics
+            }
+            _ => ExpositionFormat::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExpositionFormat::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            ExpositionFormat::OpenMetrics => {
+                "application/openmetrics-text; version=1.0.0; charset=utf-8"
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+// the two formats differ in how counters are named and how the text ends
+struct Exposition {
+    format: ExpositionFormat,
+    text: String,
+    // name of the current family, counters without `_total`
+    family: String,
+    kind: Kind,
+}
+
+#[inline]
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[inline]
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl Exposition {
+    fn new(format: ExpositionFormat) -> Self {
+        Exposition {
+            format,
+            text: String::new(),
+            family: String::new(),
+            kind: Kind::Gauge,
+        }
+    }
+
+    fn family(&mut self, name: &str, kind: Kind, help: &str) -> &mut Self {
+        self.family = name.to_string();
+        self.kind = kind;
+        let (kind, name) = match (kind, self.format) {
+            (Kind::Gauge, _) => ("gauge", name.to_string()),
+            (Kind::Counter, ExpositionFormat::OpenMetrics) => ("counter", name.to_string()),
+            (Kind::Counter, ExpositionFormat::Prometheus) => ("counter", format!("{}_total", name)),
+        };
+        let help = help.replace('\\', r"\\").replace('\n', r"\n");
+        let _ = write!(
+            self.text,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n",
+            name = name,
+            help = help,
+            kind = kind
+        );
+        self
+    }
+
+    fn sample(&mut self, labels: &[(&str, &str)], value: f64) -> &mut Self {
+        let _ = write!(self.text, "{}", self.family);
+        if self.kind == Kind::Counter {
+            self.text.push_str("_total");
+        }
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+                .collect::<Vec<String>>()
+                .join(",");
+            let _ = write!(self.text, "{{{}}}", labels);
+        }
+        let _ = writeln!(self.text, " {}", number(value));
+        self
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == ExpositionFormat::OpenMetrics {
+            self.text.push_str("# EOF\n");
+        }
+        self.text
+    }
+}
+
+#[inline]
+fn bytes(value: &Option<BitType>) -> Option<f64> {
+    value.as_ref().map(|v| v.bytes() as f64)
+}
+
+// jiffies of every mode, node_exporter's mode names
+fn core_modes(core: &CoreStat) -> [(&'static str, Option<u64>); 8] {
+    [
+        ("user", core.user),
+        ("nice", core.nice),
+        ("system", core.system),
+        ("idle", core.idle_task),
+        ("iowait", core.io_wait_unreliable),
+        ("irq", core.interrupt),
+        ("softirq", core.soft_interrupt),
+        ("steal", core.virtual_environment_stolen_time),
+    ]
+}
+
+/// The snapshot and request counters as a scrape response body.
+pub fn render(
+    snapshot: &Snapshot,
+    requests: &[(RequestKey, u64)],
+    format: ExpositionFormat,
+) -> String {
+    let mut exposition = Exposition::new(format);
+    if let Some(stat) = &snapshot.cpu_stat {
+        let ticks = clock_ticks() as f64;
+        exposition.family(
+            "serverd_cpu_seconds",
+            Kind::Counter,
+            "Seconds each CPU spent in each mode.",
+        );
+        for core in stat.specific_cpu.iter() {
+            let cpu = core.cpu.map(|cpu| cpu.to_string()).unwrap_or_default();
+            for (mode, jiffies) in core_modes(core).iter() {
+                if let Some(jiffies) = jiffies {
+                    exposition.sample(
+                        &[("cpu", cpu.as_str()), ("mode", mode)],
+                        *jiffies as f64 / ticks,
+                    );
+                }
+            }
+        }
+        let counters = [
+            (
+                "serverd_context_switches",
+                "Context switches since boot.",
+                stat.context_switch,
+            ),
+            (
+                "serverd_forks",
+                "Processes forked since boot.",
+                stat.process,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            if let Some(value) = value {
+                exposition
+                    .family(name, Kind::Counter, help)
+                    .sample(&[], *value as f64);
+            }
+        }
+        let gauges = [
+            (
+                "serverd_boot_time_seconds",
+                "Boot time, seconds since the Epoch.",
+                stat.boot_time,
+            ),
+            (
+                "serverd_procs_running",
+                "Processes in runnable state.",
+                stat.process_running,
+            ),
+            (
+                "serverd_procs_blocked",
+                "Processes blocked waiting for I/O.",
+                stat.process_blocked,
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            if let Some(value) = value {
+                exposition
+                    .family(name, Kind::Gauge, help)
+                    .sample(&[], *value as f64);
+            }
+        }
+    }
+    if let Some(mem) = &snapshot.mem_info {
+        let gauges = [
+            (
+                "serverd_memory_total_bytes",
+                "MemTotal in /proc/meminfo.",
+                bytes(&mem.mem_total),
+            ),
+            (
+                "serverd_memory_free_bytes",
+                "MemFree in /proc/meminfo.",
+                bytes(&mem.mem_free),
+            ),
+            (
+                "serverd_memory_available_bytes",
+                "MemAvailable in /proc/meminfo.",
+                bytes(&mem.mem_available),
+            ),
+            (
+                "serverd_memory_buffers_bytes",
+                "Buffers in /proc/meminfo.",
+                bytes(&mem.buffers),
+            ),
+            (
+                "serverd_memory_cached_bytes",
+                "Cached in /proc/meminfo.",
+                bytes(&mem.cached),
+            ),
+            (
+                "serverd_swap_total_bytes",
+                "SwapTotal in /proc/meminfo.",
+                bytes(&mem.swap_total),
+            ),
+            (
+                "serverd_swap_free_bytes",
+                "SwapFree in /proc/meminfo.",
+                bytes(&mem.swap_free),
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            if let Some(value) = value {
+                exposition
+                    .family(name, Kind::Gauge, help)
+                    .sample(&[], *value);
+            }
+        }
+    }
+    if let Some(load) = &snapshot.load_avg {
+        let gauges = [
+            ("serverd_load1", "1 minute load average.", load.one),
+            ("serverd_load5", "5 minute load average.", load.five),
+            ("serverd_load15", "15 minute load average.", load.fifteen),
+        ];
+        for (name, help, value) in gauges.iter() {
+            if let Some(value) = value {
+                exposition
+                    .family(name, Kind::Gauge, help)
+                    .sample(&[], *value);
+            }
+        }
+    }
+    let mut states = std::collections::BTreeMap::<String, u64>::new();
+    for process in snapshot.processes.iter() {
+        let state = process.state.map(String::from).unwrap_or_default();
+        *states.entry(state).or_insert(0) += 1;
+    }
+    exposition.family("serverd_processes", Kind::Gauge, "Processes by state.");
+    for (state, count) in states.iter() {
+        exposition.sample(&[("state", state.as_str())], *count as f64);
+    }
+    exposition
+        .family("serverd_threads", Kind::Gauge, "Threads of every process.")
+        .sample(
+            &[],
+            snapshot
+                .processes
+                .iter()
+                .map(|p| p.threads.unwrap_or(0) as f64)
+                .sum(),
+        );
+    exposition.family(
+        "serverd_http_requests",
+        Kind::Counter,
+        "HTTP requests served by method, route and status.",
+    );
+    for ((method, path, status), count) in requests.iter() {
+        exposition.sample(
+            &[
+                ("method", method.as_str()),
+                ("path", path.as_str()),
+                ("status", status.to_string().as_str()),
+            ],
+            *count as f64,
+        );
+    }
+    exposition.finish()
+}
+
+#[test]
+fn exposition_test() {
+    use crate::tools::{
+        cpu_stat::convert,
+        parse::{ParseMode, Parser},
+    };
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    let snapshot = Snapshot {
+        seq: 1,
+        cpu_stat: Some(
+            convert(
+                "cpu  200 0 100 800 0 0 0 0 0 0\ncpu0 100 0 50 400 0 0 0 0 0 0\ncpu1 100 0 50 400 0 0 0 0 0 0\nctxt 4242\n",
+                &mut parser,
+            )
+            .unwrap(),
+        ),
+        ..Snapshot::default()
+    };
+    let requests = vec![(("GET".to_string(), "/say \"hi\"".to_string(), 200), 3)];
+    let text = render(&snapshot, &requests, ExpositionFormat::Prometheus);
+    let ticks = clock_ticks() as f64;
+    assert!(text.contains("# TYPE serverd_cpu_seconds_total counter\n"));
+    assert!(text.contains(&format!(
+        "serverd_cpu_seconds_total{{cpu=\"1\",mode=\"user\"}} {}\n",
+        100.0 / ticks
+    )));
+    assert!(text.contains("serverd_context_switches_total 4242\n"));
+    assert!(text.contains(
+        "serverd_http_requests_total{method=\"GET\",path=\"/say \\\"hi\\\"\",status=\"200\"} 3\n"
+    ));
+    assert!(!text.contains("# EOF"));
+    let text = render(&snapshot, &requests, ExpositionFormat::OpenMetrics);
+    assert!(text.contains("# TYPE serverd_cpu_seconds counter\n"));
+    assert!(text.contains("serverd_context_switches_total 4242\n"));
+    assert!(text.ends_with("# EOF\n"));
+    assert_eq!(
+        ExpositionFormat::negotiate(Some("application/openmetrics-text;version=1.0.0,*/*;q=0.1")),
+        ExpositionFormat::OpenMetrics
+    );
+    assert_eq!(
+        ExpositionFormat::negotiate(None),
+        ExpositionFormat::Prometheus
+    );
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// One sampled value, `time` in unix milliseconds.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Point {
+    pub time: i64,
+    pub value: f64,
+}
+
+/// Values aggregated over `[time, time + step)`.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Bucket {
+    pub time: i64,
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub count: u64,
+}
+
+impl Bucket {
+    fn new(time: i64) -> Self {
+        Bucket {
+            time,
+            min: f64::MAX,
+            avg: 0.0,
+            max: f64::MIN,
+            count: 0,
+        }
+    }
+
+    fn merge(&mut self, other: &Bucket) {
+        let count = self.count + other.count;
+        if count == 0 {
+            return;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.avg = (self.avg * self.count as f64 + other.avg * other.count as f64) / count as f64;
+        self.count = count;
+    }
+}
+
+impl From<Point> for Bucket {
+    fn from(p: Point) -> Self {
+        Bucket {
+            time: p.time,
+            min: p.value,
+            avg: p.value,
+            max: p.value,
+            count: 1,
+        }
+    }
+}
+
+#[inline]
+fn align(time: i64, step: i64) -> i64 {
+    time - time.rem_euclid(step)
+}
+
+// fixed width buckets, the oldest one is dropped once `capacity` is reached
+#[derive(Clone, Debug)]
+struct Tier {
+    step: i64,
+    capacity: usize,
+    buckets: VecDeque<Bucket>,
+}
+
+impl Tier {
+    fn push(&mut self, bucket: Bucket) {
+        let start = align(bucket.time, self.step);
+        match self.buckets.back_mut() {
+            // a clock going backwards lands in the newest bucket
+            Some(last) if last.time >= start => last.merge(&bucket),
+            _ => {
+                let mut aligned = Bucket::new(start);
+                aligned.merge(&bucket);
+                self.buckets.push_back(aligned);
+            }
+        }
+        while self.buckets.len() > self.capacity {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Series {
+    // time of the first point ever recorded
+    since: i64,
+    raw: VecDeque<Point>,
+    tiers: Vec<Tier>,
+}
+
+/// Bounded in-memory time series: the newest `raw_capacity` points of every
+/// metric as they were sampled, plus min/avg/max buckets per tier for longer
+/// windows.
+#[derive(Clone, Debug)]
+pub struct History {
+    raw_capacity: usize,
+    // (step in milliseconds, number of buckets), finest first
+    tiers: Vec<(i64, usize)>,
+    series: HashMap<String, Series>,
+}
+
+impl Default for History {
+    // 15 minutes raw at 1s, one day by the minute, a month by the hour
+    fn default() -> Self {
+        History::new(900, vec![(60_000, 1440), (3_600_000, 720)])
+    }
+}
+
+impl History {
+    pub fn new(raw_capacity: usize, mut tiers: Vec<(i64, usize)>) -> Self {
+        tiers.retain(|(step, _)| *step > 0);
+        tiers.sort_by_key(|(step, _)| *step);
+        History {
+            raw_capacity,
+            tiers,
+            series: HashMap::new(),
+        }
+    }
+
+    fn series_mut(&mut self, metric: &str, time: i64) -> &mut Series {
+        let (raw_capacity, tiers) = (self.raw_capacity, &self.tiers);
+        let series = self
+            .series
+            .entry(metric.to_string())
+            .or_insert_with(|| Series {
+                since: time,
+                raw: VecDeque::with_capacity(raw_capacity),
+                tiers: tiers
+                    .iter()
+                    .map(|&(step, capacity)| Tier {
+                        step,
+                        capacity,
+                        buckets: VecDeque::new(),
+                    })
+                    .collect(),
+            });
+        series.since = series.since.min(time);
+        series
+    }
+
+    pub fn record(&mut self, metric: &str, time: i64, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        let raw_capacity = self.raw_capacity;
+        let series = self.series_mut(metric, time);
+        let point = Point { time, value };
+        series.raw.push_back(point);
+        while series.raw.len() > raw_capacity {
+            series.raw.pop_front();
+        }
+        series
+            .tiers
+            .iter_mut()
+            .for_each(|tier| tier.push(point.into()));
+    }
+
+    /// Put back a bucket kept elsewhere: the average of a raw point when
+    /// `step` is 0, otherwise into the tier of the same step only.  Buckets
+    /// must come oldest first, steps no tier uses are ignored.
+    pub fn restore(&mut self, metric: &str, step: i64, bucket: Bucket) {
+        if step != 0 && !self.tiers.iter().any(|&(s, _)| s == step) {
+            return;
+        }
+        let raw_capacity = self.raw_capacity;
+        let series = self.series_mut(metric, bucket.time);
+        if step == 0 {
+            series.raw.push_back(Point {
+                time: bucket.time,
+                value: bucket.avg,
+            });
+            while series.raw.len() > raw_capacity {
+                series.raw.pop_front();
+            }
+        } else if let Some(tier) = series.tiers.iter_mut().find(|tier| tier.step == step) {
+            tier.push(bucket);
+        }
+    }
+
+    /// How far back every source reaches when full, `(step, span)` in
+    /// milliseconds, raw points first given they are `interval` apart.
+    pub fn spans(&self, interval: i64) -> Vec<(i64, i64)> {
+        std::iter::once((0, self.raw_capacity as i64 * interval))
+            .chain(
+                self.tiers
+                    .iter()
+                    .map(|&(step, capacity)| (step, step * capacity as i64)),
+            )
+            .collect()
+    }
+
+    /// Raw points of `metric` newer than `time`, oldest first.  Only what
+    /// is still in the raw buffer.
+    pub fn points_after(&self, metric: &str, time: i64) -> Vec<Point> {
+        match self.series.get(metric) {
+            Some(series) => series
+                .raw
+                .iter()
+                .filter(|p| p.time > time)
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<&str> {
+        let mut metrics = self
+            .series
+            .keys()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>();
+        metrics.sort_unstable();
+        metrics
+    }
+
+    /// Buckets of `step` milliseconds covering `[from, to]`, read from the
+    /// finest source that still reaches back to `from`, or to the first
+    /// point recorded when `from` is older than that.  A step finer than
+    /// that source is widened to its resolution, a step of 0 returns raw
+    /// points as sampled.  Returns the step used.
+    pub fn query(
+        &self,
+        metric: &str,
+        from: i64,
+        to: i64,
+        step: Option<i64>,
+    ) -> Option<(i64, Vec<Bucket>)> {
+        let series = self.series.get(metric)?;
+        let raw = series
+            .raw
+            .iter()
+            .map(|&p| Bucket::from(p))
+            .collect::<Vec<Bucket>>();
+        let mut sources = vec![(0i64, raw)];
+        sources.extend(
+            series
+                .tiers
+                .iter()
+                .map(|tier| (tier.step, tier.buckets.iter().copied().collect())),
+        );
+        let oldest = from.max(series.since);
+        let reaches =
+            |buckets: &Vec<Bucket>| matches!(buckets.first(), Some(b) if b.time <= oldest);
+        let (resolution, buckets) = match sources.iter().position(|(_, b)| reaches(b)) {
+            Some(i) => sources.swap_remove(i),
+            // nothing is old enough, the longest retention is the best guess
+            None => sources.pop()?,
+        };
+        let step = step.unwrap_or(resolution).max(resolution);
+        let in_range = buckets
+            .into_iter()
+            .filter(|b| b.time >= from && b.time <= to);
+        if step == 0 {
+            return Some((step, in_range.collect()));
+        }
+        let mut result: Vec<Bucket> = Vec::new();
+        for bucket in in_range {
+            let start = align(bucket.time, step);
+            match result.last_mut() {
+                Some(last) if last.time == start => last.merge(&bucket),
+                _ => {
+                    let mut aggregated = Bucket::new(start);
+                    aggregated.merge(&bucket);
+                    result.push(aggregated);
+                }
+            }
+        }
+        Some((step, result))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SharedHistory(Arc<RwLock<History>>);
+
+impl SharedHistory {
+    pub fn new(history: History) -> Self {
+        SharedHistory(Arc::new(RwLock::new(history)))
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, History> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, History> {
+        self.0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[test]
+fn history_bounded_test() {
+    let mut history = History::new(60, vec![(10_000, 6), (60_000, 10)]);
+    // an hour of a saw tooth 0..=9 sampled every second
+    for second in 0..3600i64 {
+        history.record("saw", second * 1000, (second % 10) as f64);
+    }
+    let series = history.series.get("saw").unwrap();
+    assert_eq!(series.raw.len(), 60);
+    assert_eq!(series.raw.front().unwrap().time, 3540 * 1000);
+    assert_eq!(series.tiers[0].buckets.len(), 6);
+    assert_eq!(series.tiers[1].buckets.len(), 10);
+    let minute = series.tiers[1].buckets.back().unwrap();
+    assert_eq!(minute.count, 60);
+    assert_eq!((minute.min, minute.max), (0.0, 9.0));
+    assert!((minute.avg - 4.5).abs() < 1e-9);
+    assert!(history.query("missing", 0, 1, None).is_none());
+}
+
+#[test]
+fn history_query_test() {
+    let mut history = History::new(60, vec![(10_000, 6), (60_000, 10)]);
+    for second in 0..3600i64 {
+        history.record("line", second * 1000, second as f64);
+    }
+    // the last 30 seconds are still raw
+    let (step, buckets) = history.query("line", 3_570_000, 3_599_000, None).unwrap();
+    assert_eq!(step, 0);
+    assert_eq!(buckets.len(), 30);
+    // asking for 15s steps over raw points aggregates them
+    let (step, buckets) = history
+        .query("line", 3_570_000, 3_599_000, Some(15_000))
+        .unwrap();
+    assert_eq!(step, 15_000);
+    assert_eq!(buckets.len(), 2);
+    assert_eq!((buckets[0].min, buckets[0].max), (3570.0, 3584.0));
+    // five minutes back only the minute tier reaches
+    let (step, buckets) = history
+        .query("line", 3_300_000, 3_599_000, Some(1000))
+        .unwrap();
+    assert_eq!(step, 60_000);
+    assert_eq!(buckets.len(), 5);
+    assert!((buckets[0].avg - 3329.5).abs() < 1e-9);
+    // right after start the raw points are all there is
+    let mut fresh = History::new(60, vec![(10_000, 6), (60_000, 10)]);
+    (0..10i64).for_each(|second| fresh.record("line", 5_000 + second * 1000, second as f64));
+    let (step, buckets) = fresh.query("line", 0, 20_000, None).unwrap();
+    assert_eq!((step, buckets.len()), (0, 10));
+    // older than anything kept, fall back to the longest tier
+    let (step, buckets) = history.query("line", 0, 3_599_000, None).unwrap();
+    assert_eq!(step, 60_000);
+    assert_eq!(buckets.first().unwrap().time, 3_000_000);
+}
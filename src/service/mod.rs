@@ -1,2 +1,16 @@
+mod events;
+mod exposition;
+mod history;
+mod publish;
+mod recorder;
+mod sampler;
 mod shell;
+mod stream;
+pub use events::*;
+pub use exposition::*;
+pub use history::*;
+pub use publish::*;
+pub use recorder::*;
+pub use sampler::*;
 pub use shell::*;
+pub use stream::*;
@@ -0,0 +1,303 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    service::{History, Point, SharedSnapshot, Snapshot},
+    tools::{with_size_format, SizeFormat},
+};
+
+/// What a streaming client may subscribe to, each a part of the `Snapshot`.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Topic {
+    Cpu,
+    Mem,
+    Load,
+    Processes,
+}
+
+impl Topic {
+    pub fn parse(name: &str) -> Option<Self> {
+        serde_json::from_value(Value::String(name.to_string())).ok()
+    }
+
+    // whether `metric` of `Snapshot::metrics` belongs to this topic
+    fn owns(self, metric: &str) -> bool {
+        match self {
+            Topic::Cpu => metric.starts_with("cpu."),
+            Topic::Mem => metric.starts_with("mem.") || metric.starts_with("swap."),
+            Topic::Load => metric.starts_with("load."),
+            Topic::Processes => metric == "processes",
+        }
+    }
+
+    // missing until its collector succeeded once
+    fn data(self, snapshot: &Snapshot, units: SizeFormat) -> Option<Value> {
+        match self {
+            Topic::Cpu => snapshot
+                .cpu_usage
+                .as_ref()
+                .map(|v| json!(with_size_format(v, units))),
+            Topic::Mem => snapshot
+                .mem_info
+                .as_ref()
+                .map(|v| json!(with_size_format(v, units))),
+            Topic::Load => snapshot
+                .load_avg
+                .as_ref()
+                .map(|v| json!(with_size_format(v, units))),
+            Topic::Processes => Some(json!(with_size_format(&snapshot.processes, units))),
+        }
+    }
+}
+
+/// One update of a topic.  With `delta` set, `data` only holds what changed
+/// since the previous event of the same topic: the fields that differ, or
+/// for processes `{"changed": [...], "removed": [pid, ...]}`.
+#[derive(Clone, Serialize, Debug)]
+pub struct Event {
+    pub topic: Topic,
+    pub seq: u64,
+    // `taken_at` of the snapshot, also the SSE event id
+    pub time: i64,
+    pub delta: bool,
+    pub data: Value,
+}
+
+/// What the shared broadcast hands out, in order.
+pub enum Update {
+    Snapshot(Arc<Snapshot>),
+    // the subscriber fell behind and this many snapshots were skipped
+    Lagged(u64),
+}
+
+/// Every snapshot published from now on, ends when the sampler is gone.
+pub fn updates(snapshot: &SharedSnapshot) -> impl Stream<Item = Update> {
+    stream::unfold(snapshot.subscribe(), |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(snapshot) => Some((Update::Snapshot(snapshot), receiver)),
+            Err(RecvError::Lagged(skipped)) => Some((Update::Lagged(skipped), receiver)),
+            Err(RecvError::Closed) => None,
+        }
+    })
+}
+
+/// History of the metrics behind `topics` sampled after `time`, what a
+/// client resuming a stream missed.  Only reaches as far back as the raw
+/// points kept in `History`.
+pub fn replay(history: &History, topics: &[Topic], time: i64) -> Vec<(Topic, String, Vec<Point>)> {
+    let mut missed = Vec::new();
+    for metric in history.metrics() {
+        if let Some(topic) = topics.iter().find(|topic| topic.owns(metric)) {
+            let points = history.points_after(metric, time);
+            if !points.is_empty() {
+                missed.push((*topic, metric.to_string(), points));
+            }
+        }
+    }
+    missed
+}
+
+pub const MIN_INTERVAL: u64 = 100;
+pub const MAX_INTERVAL: u64 = 3_600_000;
+pub const DEFAULT_INTERVAL: u64 = 1000;
+
+struct Subscription {
+    // milliseconds between events
+    interval: i64,
+    delta: bool,
+    // how sizes such as rss are written, see `SizeFormat`
+    units: SizeFormat,
+    // time and data of the last event sent
+    last: Option<(i64, Value)>,
+}
+
+fn pid_of(process: &Value) -> Option<u64> {
+    process.get("pid").and_then(|pid| pid.as_u64())
+}
+
+fn delta(previous: &Value, current: &Value) -> Value {
+    match (previous, current) {
+        (Value::Object(previous), Value::Object(current)) => {
+            let mut changed = current
+                .iter()
+                .filter(|(key, value)| previous.get(key.as_str()) != Some(value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Map<String, Value>>();
+            // a field that went away is sent as null
+            for key in previous
+                .keys()
+                .filter(|key| !current.contains_key(key.as_str()))
+            {
+                changed.insert(key.clone(), Value::Null);
+            }
+            Value::Object(changed)
+        }
+        // process lists, keyed by pid
+        (Value::Array(previous), Value::Array(current)) => {
+            let previous = previous
+                .iter()
+                .filter_map(|p| Some((pid_of(p)?, p)))
+                .collect::<BTreeMap<u64, &Value>>();
+            let pids = current.iter().filter_map(pid_of).collect::<Vec<u64>>();
+            let changed = current
+                .iter()
+                .filter(|p| match pid_of(p) {
+                    Some(pid) => previous.get(&pid) != Some(p),
+                    None => true,
+                })
+                .cloned()
+                .collect::<Vec<Value>>();
+            let removed = previous
+                .keys()
+                .filter(|pid| !pids.contains(pid))
+                .copied()
+                .collect::<Vec<u64>>();
+            json!({ "changed": changed, "removed": removed })
+        }
+        _ => current.clone(),
+    }
+}
+
+/// The topics one client subscribed to, turning snapshots into the events
+/// that are due.  Transports only deliver what `events` returns.
+#[derive(Default)]
+pub struct Subscriptions {
+    topics: BTreeMap<Topic, Subscription>,
+}
+
+impl Subscriptions {
+    /// Subscribing again replaces the interval and restarts with a full
+    /// event.  Returns the interval used.
+    pub fn subscribe(
+        &mut self,
+        topic: Topic,
+        interval: Option<u64>,
+        delta: bool,
+        units: SizeFormat,
+    ) -> u64 {
+        let interval = interval
+            .unwrap_or(DEFAULT_INTERVAL)
+            .clamp(MIN_INTERVAL, MAX_INTERVAL);
+        self.topics.insert(
+            topic,
+            Subscription {
+                interval: interval as i64,
+                delta,
+                units,
+                last: None,
+            },
+        );
+        interval
+    }
+
+    pub fn unsubscribe(&mut self, topic: Topic) -> bool {
+        self.topics.remove(&topic).is_some()
+    }
+
+    pub fn topics(&self) -> Vec<Topic> {
+        self.topics.keys().copied().collect()
+    }
+
+    /// Events of every topic whose interval has passed since its last one.
+    pub fn events(&mut self, snapshot: &Snapshot) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (topic, subscription) in self.topics.iter_mut() {
+            // samples drift by a few milliseconds, allow a tenth of the interval
+            let due = match &subscription.last {
+                Some((time, _)) => {
+                    snapshot.taken_at - time >= subscription.interval - subscription.interval / 10
+                }
+                None => true,
+            };
+            if !due {
+                continue;
+            }
+            let data = match topic.data(snapshot, subscription.units) {
+                Some(data) => data,
+                None => continue,
+            };
+            let event = match (&subscription.last, subscription.delta) {
+                (Some((_, previous)), true) => Event {
+                    topic: *topic,
+                    seq: snapshot.seq,
+                    time: snapshot.taken_at,
+                    delta: true,
+                    data: delta(previous, &data),
+                },
+                _ => Event {
+                    topic: *topic,
+                    seq: snapshot.seq,
+                    time: snapshot.taken_at,
+                    delta: false,
+                    data: data.clone(),
+                },
+            };
+            subscription.last = Some((snapshot.taken_at, data));
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[test]
+fn subscriptions_test() {
+    use crate::tools::pid_info::ProcessInfo;
+    let process = |pid: u32, threads: u32| {
+        let mut info: ProcessInfo =
+            serde_json::from_value(json!({ "pid": pid, "command_line": [] })).unwrap();
+        info.threads = Some(threads);
+        info
+    };
+    let snapshot = |seq: u64, processes: Vec<ProcessInfo>| Snapshot {
+        seq,
+        taken_at: seq as i64 * 1000,
+        processes,
+        ..Snapshot::default()
+    };
+    let mut subscriptions = Subscriptions::default();
+    assert_eq!(
+        subscriptions.subscribe(Topic::Processes, Some(2000), true, SizeFormat::Bytes),
+        2000
+    );
+    assert_eq!(
+        subscriptions.subscribe(Topic::Cpu, Some(1), false, SizeFormat::Bytes),
+        MIN_INTERVAL
+    );
+    // cpu usage is not known yet, so only processes, and in full
+    let events = subscriptions.events(&snapshot(1, vec![process(1, 1), process(2, 1)]));
+    assert_eq!(events.len(), 1);
+    assert!(!events[0].delta);
+    assert_eq!(events[0].data.as_array().unwrap().len(), 2);
+    // one second later is not due yet
+    assert!(subscriptions
+        .events(&snapshot(2, vec![process(1, 1)]))
+        .is_empty());
+    let events = subscriptions.events(&snapshot(3, vec![process(1, 4), process(3, 1)]));
+    assert!(events[0].delta);
+    assert_eq!(events[0].data["removed"], json!([2]));
+    let changed = events[0].data["changed"].as_array().unwrap();
+    assert_eq!(
+        changed.iter().filter_map(pid_of).collect::<Vec<u64>>(),
+        [1, 3]
+    );
+    assert!(subscriptions.unsubscribe(Topic::Processes));
+    assert!(!subscriptions.unsubscribe(Topic::Processes));
+    assert_eq!(subscriptions.topics(), [Topic::Cpu]);
+    assert_eq!(Topic::parse("mem"), Some(Topic::Mem));
+    // sizes as asked for with `units`
+    let mut human = Subscriptions::default();
+    human.subscribe(Topic::Processes, None, false, SizeFormat::Iec);
+    let big: ProcessInfo =
+        serde_json::from_value(json!({ "pid": 4, "rss": 2_097_152, "command_line": [] })).unwrap();
+    let events = human.events(&snapshot(1, vec![big]));
+    assert_eq!(events[0].data[0]["rss"], json!("2.0 MiB"));
+    assert_eq!(
+        delta(&json!({"a": 1, "b": 2, "c": 3}), &json!({"a": 1, "b": 5})),
+        json!({"b": 5, "c": null})
+    );
+}
@@ -0,0 +1,328 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use actix::prelude::*;
+use chrono::Utc;
+
+use crate::{
+    dao::{DaoResult, MetricRow, MetricsRepository},
+    service::{Bucket, SharedHistory},
+    tools::{log, LogLevel},
+};
+
+const DAY: i64 = 86_400_000;
+
+/// How long rows of every step are kept in the database.
+#[derive(Clone, Debug)]
+pub struct Retention {
+    /*
+       (step, keep) in milliseconds, finest first.  The
+       first step is 0, the raw samples, every other step
+       is rolled up from the one before it.
+    */
+    steps: Vec<(i64, i64)>,
+}
+
+impl Default for Retention {
+    // the same steps `History` keeps by default
+    fn default() -> Self {
+        Retention {
+            steps: vec![(0, DAY), (60_000, 30 * DAY), (3_600_000, 365 * DAY)],
+        }
+    }
+}
+
+impl Retention {
+    /// `step=keep` pairs in seconds separated by commas, such as
+    /// `0=86400,60=2592000`.  Raw samples (step 0) must be kept, and every
+    /// step must be a multiple of the one before it.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut steps = value
+            .split(',')
+            .map(|pair| {
+                let (step, keep) = pair.split_once('=')?;
+                Some((
+                    step.trim().parse::<i64>().ok()? * 1000,
+                    keep.trim().parse::<i64>().ok()? * 1000,
+                ))
+            })
+            .collect::<Option<Vec<(i64, i64)>>>()?;
+        steps.sort_by_key(|(step, _)| *step);
+        let valid = matches!(steps.first(), Some((0, _)))
+            && steps.iter().all(|(step, keep)| *step >= 0 && *keep > 0)
+            && steps
+                .windows(2)
+                .all(|w| w[1].0 > w[0].0 && (w[0].0 == 0 || w[1].0 % w[0].0 == 0));
+        if valid {
+            Some(Retention { steps })
+        } else {
+            None
+        }
+    }
+
+    fn raw_keep(&self) -> i64 {
+        self.steps.first().map(|(_, keep)| *keep).unwrap_or(0)
+    }
+}
+
+#[inline]
+fn align(time: i64, step: i64) -> i64 {
+    time - time.rem_euclid(step)
+}
+
+/// Roll samples in `[from, to)` up into every step, finest first, so each
+/// step sees the buckets just written below it.  Buckets overlapping the
+/// window are rebuilt whole.
+pub async fn rollup(
+    repository: &dyn MetricsRepository,
+    retention: &Retention,
+    from: i64,
+    to: i64,
+) -> DaoResult<u64> {
+    let mut written = 0;
+    for pair in retention.steps.windows(2) {
+        let (source, step) = (pair[0].0, pair[1].0);
+        written += repository
+            .rollup(source, step, align(from, step), to)
+            .await?;
+    }
+    Ok(written)
+}
+
+/// Delete rows every step no longer keeps.
+pub async fn expire(
+    repository: &dyn MetricsRepository,
+    retention: &Retention,
+    now: i64,
+) -> DaoResult<u64> {
+    let mut deleted = 0;
+    for &(step, keep) in retention.steps.iter() {
+        deleted += repository.expire(step, now - keep).await?;
+    }
+    Ok(deleted)
+}
+
+/// Refill `history` from the database after a restart, each of its sources
+/// from the rows of the same step.  Samples are assumed `interval`
+/// milliseconds apart.  Returns the number of rows restored.
+pub async fn restore_history(
+    repository: &dyn MetricsRepository,
+    retention: &Retention,
+    history: &SharedHistory,
+    interval: i64,
+    now: i64,
+) -> DaoResult<usize> {
+    // the buckets still open when the last run stopped
+    rollup(repository, retention, now - retention.raw_keep(), now).await?;
+    let spans = history.read().spans(interval);
+    let mut restored = 0;
+    for (step, span) in spans {
+        let rows = repository.load(step, now - span).await?;
+        restored += rows.len();
+        let mut history = history.write();
+        for row in rows {
+            history.restore(
+                row.metric.as_str(),
+                step,
+                Bucket {
+                    time: row.time,
+                    min: row.min,
+                    avg: row.avg,
+                    max: row.max,
+                    count: row.count.max(0) as u64,
+                },
+            );
+        }
+    }
+    Ok(restored)
+}
+
+/// Metrics of one sample, sent by the `Sampler`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Record {
+    // unix time in milliseconds
+    pub time: i64,
+    pub metrics: Vec<(String, f64)>,
+}
+
+/// Writes samples to a `MetricsRepository` in batches and runs the rollup
+/// and retention jobs.  While the database is unreachable rows are kept in
+/// memory, up to `MAX_PENDING`, dropping the oldest.
+pub struct MetricsRecorder {
+    repository: Arc<dyn MetricsRepository>,
+    retention: Retention,
+    pending: VecDeque<MetricRow>,
+    flushing: bool,
+    maintaining: bool,
+    // end of the last successful rollup
+    rolled_to: Option<i64>,
+}
+
+const BATCH_SIZE: usize = 512;
+const MAX_PENDING: usize = 64 * BATCH_SIZE;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+const MAINTAIN_INTERVAL: Duration = Duration::from_secs(300);
+
+impl MetricsRecorder {
+    pub fn new(repository: Arc<dyn MetricsRepository>, retention: Retention) -> Self {
+        MetricsRecorder {
+            repository,
+            retention,
+            pending: VecDeque::new(),
+            flushing: false,
+            maintaining: false,
+            rolled_to: None,
+        }
+    }
+
+    fn trim(&mut self) {
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            log(LogLevel::Warn(
+                std::format!("metrics backlog full, dropped {} rows", dropped).as_str(),
+            ));
+        }
+    }
+
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if self.flushing || self.pending.is_empty() {
+            return;
+        }
+        self.flushing = true;
+        let rows = self.pending.drain(..).collect::<Vec<MetricRow>>();
+        let repository = self.repository.clone();
+        ctx.spawn(
+            async move {
+                let result = repository.insert(&rows).await.map_err(|e| e.to_string());
+                (result, rows)
+            }
+            .into_actor(self)
+            .map(|(result, rows), recorder, _| {
+                recorder.flushing = false;
+                if let Err(e) = result {
+                    log(LogLevel::Warn(
+                        std::format!("persist {} metric rows failed: {}", rows.len(), e).as_str(),
+                    ));
+                    // keep them ahead of what arrived meanwhile
+                    for row in rows.into_iter().rev() {
+                        recorder.pending.push_front(row);
+                    }
+                    recorder.trim();
+                }
+            }),
+        );
+    }
+
+    fn maintain(&mut self, ctx: &mut Context<Self>) {
+        if self.maintaining {
+            return;
+        }
+        self.maintaining = true;
+        let now = Utc::now().timestamp_millis();
+        // rows of the last window may have been flushed after it ran
+        let from = match self.rolled_to {
+            Some(to) => to - 2 * FLUSH_INTERVAL.as_millis() as i64,
+            None => now - self.retention.raw_keep(),
+        };
+        let (repository, retention) = (self.repository.clone(), self.retention.clone());
+        ctx.spawn(
+            async move {
+                rollup(&*repository, &retention, from, now).await?;
+                expire(&*repository, &retention, now).await
+            }
+            .into_actor(self)
+            .map(move |result, recorder, _| {
+                recorder.maintaining = false;
+                match result {
+                    Ok(_) => recorder.rolled_to = Some(now),
+                    Err(e) => log(LogLevel::Warn(
+                        std::format!("metrics rollup failed: {}", e).as_str(),
+                    )),
+                }
+            }),
+        );
+    }
+}
+
+impl Actor for MetricsRecorder {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(FLUSH_INTERVAL, |recorder, ctx| recorder.flush(ctx));
+        ctx.run_interval(MAINTAIN_INTERVAL, |recorder, ctx| recorder.maintain(ctx));
+    }
+}
+
+impl Handler<Record> for MetricsRecorder {
+    type Result = ();
+    fn handle(&mut self, record: Record, ctx: &mut Self::Context) {
+        self.pending.extend(
+            record
+                .metrics
+                .iter()
+                .map(|(metric, value)| MetricRow::sample(metric.as_str(), record.time, *value)),
+        );
+        self.trim();
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush(ctx);
+        }
+    }
+}
+
+#[tokio::test]
+async fn recorder_rollup_test() {
+    use crate::dao::MemoryMetricsRepository;
+    let repository = MemoryMetricsRepository::default();
+    let retention = Retention::parse("0=3600,60=86400,3600=604800").unwrap();
+    // three hours of a saw tooth 0..=9 every second
+    let rows = (0..3 * 3600i64)
+        .map(|second| MetricRow::sample("saw", second * 1000, (second % 10) as f64))
+        .collect::<Vec<MetricRow>>();
+    repository.insert(&rows).await.unwrap();
+    let now = 3 * 3_600_000;
+    rollup(&repository, &retention, 0, now).await.unwrap();
+    let minutes = repository.rows(60_000);
+    assert_eq!(minutes.len(), 180);
+    assert_eq!(minutes[0].count, 60);
+    assert!((minutes[0].avg - 4.5).abs() < 1e-9);
+    let hours = repository.rows(3_600_000);
+    assert_eq!(hours.len(), 3);
+    assert_eq!(hours[2].count, 3600);
+    assert_eq!((hours[2].min, hours[2].max), (0.0, 9.0));
+    // rolling the same window again rewrites the same buckets
+    rollup(&repository, &retention, now - 90_000, now)
+        .await
+        .unwrap();
+    assert_eq!(repository.rows(60_000).len(), 180);
+    assert_eq!(repository.rows(3_600_000)[2].count, 3600);
+    // raw samples are kept an hour, rollups much longer
+    expire(&repository, &retention, now).await.unwrap();
+    assert_eq!(repository.rows(0).len(), 3600);
+    assert_eq!(repository.rows(0)[0].time, 2 * 3_600_000);
+    assert_eq!(repository.rows(60_000).len(), 180);
+    assert!(Retention::parse("60=86400").is_none());
+    assert!(Retention::parse("0=3600,90=86400,120=604800").is_none());
+}
+
+#[tokio::test]
+async fn restore_history_test() {
+    use crate::{dao::MemoryMetricsRepository, service::History};
+    let repository = MemoryMetricsRepository::default();
+    let retention = Retention::parse("0=3600,10=86400,60=604800").unwrap();
+    let rows = (0..3600i64)
+        .map(|second| MetricRow::sample("line", second * 1000, second as f64))
+        .collect::<Vec<MetricRow>>();
+    repository.insert(&rows).await.unwrap();
+    let history = SharedHistory::new(History::new(60, vec![(10_000, 6), (60_000, 10)]));
+    let restored = restore_history(&repository, &retention, &history, 1000, 3_600_000)
+        .await
+        .unwrap();
+    // 60 raw points, 6 ten second buckets and 10 minutes
+    assert_eq!(restored, 76);
+    let history = history.read();
+    let (step, points) = history.query("line", 3_570_000, 3_600_000, None).unwrap();
+    assert_eq!((step, points.len()), (0, 30));
+    let (step, minutes) = history.query("line", 3_000_000, 3_600_000, None).unwrap();
+    assert_eq!((step, minutes.len()), (60_000, 10));
+    assert!((minutes[0].avg - 3029.5).abs() < 1e-9);
+}
@@ -0,0 +1,453 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, RwLock, RwLockReadGuard},
+    time::Duration,
+};
+
+use actix::prelude::*;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    service::{Record, SharedHistory},
+    tools::{
+        cgroup::{cgroup_rates, cgroups, Cgroup, CgroupRate},
+        cpu_freq::{cpu_freq, CpuFreqPolicy},
+        cpu_info::{cpu_info, CpuInfo},
+        cpu_stat::{cpu_stat, CpuStat},
+        cpu_topology::{cpu_topology, numa_nodes, CpuTopology, NumaNode},
+        cpu_usage::{cpu_usage, CpuUsage},
+        disk_stats::{disk_rates, disk_stats, DiskRate, DiskStat},
+        filesystems::{filesystems, Filesystem, UsageProbe},
+        interrupts::{interrupt_rates, interrupts, InterruptRate, Interrupts},
+        load_avg::{load_avg, LoadAvg},
+        log,
+        mem_info::{mem_info, memory_summary, MemoInfo, MemorySummary},
+        net_dev::{net_dev, net_rates, NetInterface, NetRate},
+        parse::{ParseMode, Parsed, ProcParseError},
+        pid_info::{pid_info, ProcessInfo},
+        pressure::{pressure, top_cgroups_pressure, Pressure, StallTime},
+        sensors::{sensors, Sensors},
+        system_summary::{system_summary, SystemSummary},
+        vm_stat::{vm_rate, vm_stat, VmRate, VmStat},
+        AccountFiles, LogLevel, ProcRoot, SysRoot,
+    },
+};
+
+/// Latest reading of every collector, what handlers answer from.
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct Snapshot {
+    // number of samples taken, 0 until the first one is published
+    pub seq: u64,
+    // unix time in milliseconds
+    pub taken_at: i64,
+    pub cpu_info: Vec<CpuInfo>,
+    pub cpu_topology: Option<CpuTopology>,
+    pub numa_nodes: Vec<NumaNode>,
+    pub cpu_freq: Vec<CpuFreqPolicy>,
+    pub cpu_stat: Option<CpuStat>,
+    // needs two readings, so it is missing on the first sample
+    pub cpu_usage: Option<CpuUsage>,
+    pub mem_info: Option<MemoInfo>,
+    // free(1) of `mem_info`
+    pub memory: Option<MemorySummary>,
+    pub vm_stat: Option<VmStat>,
+    // needs two readings, so it is missing on the first sample
+    pub vm_rate: Option<VmRate>,
+    pub interrupts: Option<Interrupts>,
+    // per second, empty on the first sample
+    pub interrupt_rates: Vec<InterruptRate>,
+    pub load_avg: Option<LoadAvg>,
+    pub pressure: Option<Pressure>,
+    // by the name of a cgroup right below the root
+    pub cgroup_pressure: BTreeMap<String, Pressure>,
+    // the cgroup v2 tree, and per second usage in tree order
+    pub cgroups: Option<Cgroup>,
+    pub cgroup_rates: Vec<CgroupRate>,
+    pub disk_stats: Vec<DiskStat>,
+    // per second, empty on the first sample
+    pub disk_rates: Vec<DiskRate>,
+    pub net_interfaces: Vec<NetInterface>,
+    // per second, empty on the first sample
+    pub net_rates: Vec<NetRate>,
+    pub filesystems: Vec<Filesystem>,
+    pub sensors: Option<Sensors>,
+    pub summary: Option<SystemSummary>,
+    pub processes: Vec<ProcessInfo>,
+    // fields the parsers skipped, the reading goes on without them
+    pub parse_warnings: Vec<ProcParseError>,
+}
+
+impl Snapshot {
+    /// Scalar values worth keeping over time, keyed by metric name.
+    pub fn metrics(&self) -> Vec<(String, f64)> {
+        let mut metrics = Vec::new();
+        if let Some(usage) = &self.cpu_usage {
+            metrics.push(("cpu.busy".to_string(), usage.total.busy));
+            metrics.push(("cpu.user".to_string(), usage.total.user));
+            metrics.push(("cpu.system".to_string(), usage.total.system));
+            metrics.push(("cpu.io_wait".to_string(), usage.total.io_wait));
+        }
+        // in kHz
+        for policy in self.cpu_freq.iter() {
+            if let Some(cur) = policy.cur_freq {
+                metrics.push((std::format!("cpufreq.policy{}.cur", policy.id), cur as f64));
+            }
+        }
+        if let Some(memory) = &self.memory {
+            if let (Some(used), Some(available)) = (memory.used, memory.available) {
+                metrics.push(("mem.used".to_string(), used.bytes() as f64));
+                metrics.push(("mem.available".to_string(), available.bytes() as f64));
+            }
+            if let Some(used) = memory.swap_used {
+                metrics.push(("swap.used".to_string(), used.bytes() as f64));
+            }
+        }
+        if let Some(vm) = &self.vm_rate {
+            metrics.push(("vm.page_faults".to_string(), vm.page_faults));
+            metrics.push(("vm.major_faults".to_string(), vm.major_faults));
+            metrics.push(("vm.swap_in".to_string(), vm.swap_in));
+            metrics.push(("vm.swap_out".to_string(), vm.swap_out));
+        }
+        if !self.interrupt_rates.is_empty() {
+            metrics.push((
+                "interrupts".to_string(),
+                self.interrupt_rates.iter().map(|r| r.per_second).sum(),
+            ));
+        }
+        if let Some(load) = &self.load_avg {
+            let loads = [
+                ("load.1", load.one),
+                ("load.5", load.five),
+                ("load.15", load.fifteen),
+            ];
+            for (metric, value) in loads.iter() {
+                if let Some(value) = value {
+                    metrics.push((metric.to_string(), *value));
+                }
+            }
+        }
+        if let Some(pressure) = &self.pressure {
+            let resources = [
+                ("cpu", &pressure.cpu),
+                ("memory", &pressure.memory),
+                ("io", &pressure.io),
+            ];
+            for (resource, stalls) in resources.iter() {
+                let stalls = match stalls {
+                    Some(stalls) => stalls,
+                    None => continue,
+                };
+                let kinds: [(&str, &Option<StallTime>); 2] =
+                    [("some", &stalls.some), ("full", &stalls.full)];
+                for (kind, stall) in kinds.iter() {
+                    if let Some(stall) = stall {
+                        metrics.push((
+                            std::format!("pressure.{}.{}.avg10", resource, kind),
+                            stall.avg10,
+                        ));
+                    }
+                }
+            }
+        }
+        metrics.push(("processes".to_string(), self.processes.len() as f64));
+        metrics
+    }
+}
+
+// how long a sample waits for the usage of a filesystem
+const STATVFS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// snapshots a subscriber may fall behind before it skips to the newest
+const UPDATES_CAPACITY: usize = 4;
+
+/// The latest snapshot for handlers, and every new one for subscribers.
+#[derive(Clone)]
+pub struct SharedSnapshot {
+    latest: Arc<RwLock<Arc<Snapshot>>>,
+    updates: broadcast::Sender<Arc<Snapshot>>,
+}
+
+impl Default for SharedSnapshot {
+    fn default() -> Self {
+        SharedSnapshot {
+            latest: Arc::default(),
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+        }
+    }
+}
+
+impl SharedSnapshot {
+    // a panic while publishing must not take every handler down with it
+    pub fn read(&self) -> RwLockReadGuard<'_, Arc<Snapshot>> {
+        self.latest
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Snapshots published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Snapshot>> {
+        self.updates.subscribe()
+    }
+
+    fn publish(&self, snapshot: Snapshot) {
+        let snapshot = Arc::new(snapshot);
+        *self
+            .latest
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = snapshot.clone();
+        // no subscriber is not an error
+        let _ = self.updates.send(snapshot);
+    }
+}
+
+/// Reads /proc on its own arbiter every `interval`, publishes the result
+/// into a `SharedSnapshot` and records its metrics into a `SharedHistory`.
+pub struct Sampler {
+    interval: Duration,
+    snapshot: SharedSnapshot,
+    history: SharedHistory,
+    // where samples are persisted, if anywhere
+    recorder: Option<Recipient<Record>>,
+    proc: ProcRoot,
+    sys: SysRoot,
+    accounts: AccountFiles,
+    seq: u64,
+    cpu_info: Parsed<Vec<CpuInfo>>,
+    cpu_topology: Option<CpuTopology>,
+    cpu_stat: Option<CpuStat>,
+    // previous readings rates are computed against
+    taken_at: i64,
+    disk_stats: Vec<DiskStat>,
+    net_interfaces: Vec<NetInterface>,
+    cgroups: Option<Cgroup>,
+    vm_stat: Option<VmStat>,
+    interrupts: Option<Interrupts>,
+    // statvfs(3) calls still pending on an unreachable mount
+    usage_probe: UsageProbe,
+    // warnings already logged, as "file: field"
+    warned: BTreeSet<String>,
+}
+
+#[inline]
+fn collect<T>(name: &str, result: std::io::Result<T>) -> Option<T> {
+    match result {
+        Ok(v) => Some(v),
+        Err(e) => {
+            log(LogLevel::Warn(
+                std::format!("sample {} failed: {}", name, e).as_str(),
+            ));
+            None
+        }
+    }
+}
+
+// the data of a lenient parse, its warnings are moved into `warnings`
+#[inline]
+fn collect_parsed<T>(
+    name: &str,
+    result: std::io::Result<Parsed<T>>,
+    warnings: &mut Vec<ProcParseError>,
+) -> Option<T> {
+    collect(name, result).map(|parsed| {
+        warnings.extend(parsed.warnings);
+        parsed.data
+    })
+}
+
+impl Sampler {
+    pub fn new(interval: Duration, snapshot: SharedSnapshot, history: SharedHistory) -> Self {
+        Sampler {
+            interval,
+            snapshot,
+            history,
+            recorder: None,
+            proc: ProcRoot::default(),
+            sys: SysRoot::default(),
+            accounts: AccountFiles::default(),
+            seq: 0,
+            cpu_info: Parsed::default(),
+            cpu_topology: None,
+            cpu_stat: None,
+            taken_at: 0,
+            disk_stats: Vec::new(),
+            net_interfaces: Vec::new(),
+            cgroups: None,
+            vm_stat: None,
+            interrupts: None,
+            usage_probe: UsageProbe::default(),
+            warned: BTreeSet::new(),
+        }
+    }
+
+    pub fn record_to(mut self, recorder: Recipient<Record>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Read another procfs, sysfs and account files than the ones of this
+    /// machine.
+    pub fn read_from(mut self, proc: ProcRoot, sys: SysRoot, accounts: AccountFiles) -> Self {
+        self.proc = proc;
+        self.sys = sys;
+        self.accounts = accounts;
+        self
+    }
+
+    // a warning is logged once, but stays in every snapshot it applies to
+    fn warn(&mut self, warnings: &[ProcParseError]) {
+        for warning in warnings.iter() {
+            if self
+                .warned
+                .insert(std::format!("{}: {}", warning.file, warning.field))
+            {
+                log(LogLevel::Warn(std::format!("skipped {}", warning).as_str()));
+            }
+        }
+    }
+
+    fn sample(&mut self) {
+        let mut parse_warnings = self.cpu_info.warnings.clone();
+        let cpu_stat = collect_parsed(
+            "cpu_stat",
+            cpu_stat(&self.proc, ParseMode::Lenient),
+            &mut parse_warnings,
+        );
+        let cpu_usage = match (&self.cpu_stat, &cpu_stat) {
+            (Some(p), Some(c)) => Some(cpu_usage(p, c)),
+            _ => None,
+        };
+        self.cpu_stat = cpu_stat.clone();
+        let taken_at = Utc::now().timestamp_millis();
+        let seconds = (taken_at - self.taken_at) as f64 / 1000.0;
+        let disk_stats = collect("disk_stats", disk_stats(&self.proc)).unwrap_or_default();
+        let disk_rates = disk_rates(&self.disk_stats, &disk_stats, seconds);
+        self.disk_stats = disk_stats.clone();
+        let net_interfaces = collect("net_dev", net_dev(&self.proc, &self.sys)).unwrap_or_default();
+        let net_rates = net_rates(&self.net_interfaces, &net_interfaces, seconds);
+        self.net_interfaces = net_interfaces.clone();
+        let cgroups = collect("cgroups", cgroups(&self.sys)).flatten();
+        let cgroup_rates = match (&self.cgroups, &cgroups) {
+            (Some(p), Some(c)) => cgroup_rates(p, c, seconds),
+            _ => Vec::new(),
+        };
+        self.cgroups = cgroups.clone();
+        let vm_stat = collect_parsed(
+            "vm_stat",
+            vm_stat(&self.proc, ParseMode::Lenient),
+            &mut parse_warnings,
+        );
+        let vm_rate = match (&self.vm_stat, &vm_stat) {
+            (Some(p), Some(c)) => vm_rate(p, c, seconds),
+            _ => None,
+        };
+        self.vm_stat = vm_stat.clone();
+        let interrupts = collect_parsed(
+            "interrupts",
+            interrupts(&self.proc, ParseMode::Lenient),
+            &mut parse_warnings,
+        );
+        let interrupt_rates = match (&self.interrupts, &interrupts) {
+            (Some(p), Some(c)) => interrupt_rates(p, c, seconds),
+            _ => Vec::new(),
+        };
+        self.interrupts = interrupts.clone();
+        self.taken_at = taken_at;
+        let load_avg = collect("load_avg", load_avg(&self.proc));
+        let summary = collect(
+            "summary",
+            system_summary(&self.proc, &self.accounts, load_avg.clone()),
+        );
+        let mem_info = collect_parsed(
+            "mem_info",
+            mem_info(&self.proc, ParseMode::Lenient),
+            &mut parse_warnings,
+        );
+        let pressure = collect_parsed(
+            "pressure",
+            pressure(&self.proc, ParseMode::Lenient),
+            &mut parse_warnings,
+        );
+        let cgroup_pressure = collect_parsed(
+            "cgroup_pressure",
+            top_cgroups_pressure(&self.sys, ParseMode::Lenient),
+            &mut parse_warnings,
+        )
+        .unwrap_or_default();
+        self.warn(&parse_warnings);
+        self.seq += 1;
+        let snapshot = Snapshot {
+            seq: self.seq,
+            taken_at,
+            cpu_info: self.cpu_info.data.clone(),
+            cpu_topology: self.cpu_topology.clone(),
+            numa_nodes: collect("numa_nodes", numa_nodes(&self.sys)).unwrap_or_default(),
+            cpu_freq: collect("cpu_freq", cpu_freq(&self.sys)).unwrap_or_default(),
+            cpu_stat,
+            cpu_usage,
+            memory: mem_info.as_ref().map(memory_summary),
+            mem_info,
+            vm_stat,
+            vm_rate,
+            interrupts,
+            interrupt_rates,
+            load_avg,
+            pressure,
+            cgroup_pressure,
+            cgroups,
+            cgroup_rates,
+            disk_stats,
+            disk_rates,
+            net_interfaces,
+            net_rates,
+            filesystems: collect(
+                "filesystems",
+                filesystems(&self.proc, &mut self.usage_probe, STATVFS_TIMEOUT),
+            )
+            .unwrap_or_default(),
+            sensors: collect("sensors", sensors(&self.sys)),
+            summary,
+            processes: collect("pid_info", pid_info(&self.proc, &self.accounts))
+                .unwrap_or_default(),
+            parse_warnings,
+        };
+        let metrics = snapshot.metrics();
+        {
+            let mut history = self.history.write();
+            for (metric, value) in metrics.iter() {
+                history.record(metric.as_str(), snapshot.taken_at, *value);
+            }
+        }
+        if let Some(recorder) = &self.recorder {
+            // a recorder that went away only costs persistence
+            if let Err(e) = recorder.do_send(Record {
+                time: snapshot.taken_at,
+                metrics,
+            }) {
+                log(LogLevel::Warn(
+                    std::format!("record sample failed: {}", e).as_str(),
+                ));
+            }
+        }
+        self.snapshot.publish(snapshot);
+    }
+}
+
+impl Actor for Sampler {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // cpuinfo and the topology do not change while running, read them once
+        self.cpu_info = collect(
+            "cpu_info",
+            cpu_info(&self.proc, &self.sys, ParseMode::Lenient),
+        )
+        .unwrap_or_default();
+        self.cpu_topology = collect("cpu_topology", cpu_topology(&self.sys));
+        self.sample();
+        ctx.run_interval(self.interval, |sampler, _| sampler.sample());
+        log(LogLevel::Info(
+            std::format!("sampler started, interval {:?}", self.interval).as_str(),
+        ));
+    }
+}
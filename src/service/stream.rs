@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix_web_actors::ws::{CloseCode, CloseReason, Message, ProtocolError, WebsocketContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    service::{updates, Event, SharedSnapshot, Subscriptions, Topic, Update},
+    tools::{log, LogLevel, SizeFormat},
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// no pong nor any other frame for this long and the client is gone
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+// per connection: biggest request accepted, and requests per window
+const MAX_REQUEST_SIZE: usize = 4096;
+const MAX_REQUESTS: u32 = 30;
+const REQUEST_WINDOW: Duration = Duration::from_secs(10);
+
+/// What a client sends, e.g.
+/// `{"op":"subscribe","topic":"cpu","interval":1000,"delta":true,"units":"iec"}`
+/// or `{"op":"unsubscribe","topic":"cpu"}`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Request {
+    Subscribe {
+        topic: Topic,
+        // milliseconds, clamped to MIN_INTERVAL..=MAX_INTERVAL
+        interval: Option<u64>,
+        #[serde(default)]
+        delta: bool,
+        // sizes in bytes unless asked otherwise, as /os/proc?units=
+        #[serde(default)]
+        units: SizeFormat,
+    },
+    Unsubscribe {
+        topic: Topic,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Reply {
+    Subscribed { topic: Topic, interval: u64 },
+    Unsubscribed { topic: Topic },
+    Update(Event),
+    // snapshots skipped because this connection fell behind
+    Lagged { skipped: u64 },
+    Error { info: String },
+}
+
+/// Pushes the topics a client subscribed to over a websocket.  Snapshots
+/// are only taken off the shared broadcast while the socket keeps up, a
+/// client that falls behind skips to the newest one and is told so.
+pub struct MetricStream {
+    snapshot: SharedSnapshot,
+    subscriptions: Subscriptions,
+    heartbeat: Instant,
+    // start of the current request window and requests seen in it
+    window: (Instant, u32),
+}
+
+impl MetricStream {
+    pub fn new(snapshot: SharedSnapshot) -> Self {
+        MetricStream {
+            snapshot,
+            subscriptions: Subscriptions::default(),
+            heartbeat: Instant::now(),
+            window: (Instant::now(), 0),
+        }
+    }
+
+    fn reply(&self, reply: Reply, ctx: &mut WebsocketContext<Self>) {
+        match serde_json::to_string(&reply) {
+            Ok(text) => ctx.text(text),
+            Err(e) => log(LogLevel::Error(
+                std::format!("encode stream reply failed: {}", e).as_str(),
+            )),
+        }
+    }
+
+    fn publish(&mut self, ctx: &mut WebsocketContext<Self>) {
+        let snapshot = self.snapshot.read().clone();
+        if snapshot.seq == 0 {
+            return;
+        }
+        for event in self.subscriptions.events(&snapshot) {
+            self.reply(Reply::Update(event), ctx);
+        }
+    }
+
+    // false once the client sent more than MAX_REQUESTS in a window
+    fn allow_request(&mut self) -> bool {
+        if self.window.0.elapsed() >= REQUEST_WINDOW {
+            self.window = (Instant::now(), 0);
+        }
+        self.window.1 += 1;
+        self.window.1 <= MAX_REQUESTS
+    }
+
+    fn request(&mut self, text: &str, ctx: &mut WebsocketContext<Self>) {
+        if !self.allow_request() {
+            ctx.close(Some(CloseReason {
+                code: CloseCode::Policy,
+                description: Some("too many requests".to_string()),
+            }));
+            ctx.stop();
+            return;
+        }
+        if text.len() > MAX_REQUEST_SIZE {
+            return self.reply(
+                Reply::Error {
+                    info: "request too large".to_string(),
+                },
+                ctx,
+            );
+        }
+        match serde_json::from_str::<Request>(text) {
+            Ok(Request::Subscribe {
+                topic,
+                interval,
+                delta,
+                units,
+            }) => {
+                let interval = self.subscriptions.subscribe(topic, interval, delta, units);
+                self.reply(Reply::Subscribed { topic, interval }, ctx);
+                // the first event does not wait for the next sample
+                self.publish(ctx);
+            }
+            Ok(Request::Unsubscribe { topic }) => {
+                if self.subscriptions.unsubscribe(topic) {
+                    self.reply(Reply::Unsubscribed { topic }, ctx);
+                } else {
+                    self.reply(
+                        Reply::Error {
+                            info: "not subscribed".to_string(),
+                        },
+                        ctx,
+                    );
+                }
+            }
+            Err(e) => self.reply(
+                Reply::Error {
+                    info: e.to_string(),
+                },
+                ctx,
+            ),
+        }
+    }
+}
+
+impl Actor for MetricStream {
+    type Context = WebsocketContext<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.add_stream(updates(&self.snapshot));
+        ctx.run_interval(HEARTBEAT_INTERVAL, |stream, ctx| {
+            if stream.heartbeat.elapsed() > CLIENT_TIMEOUT {
+                log(LogLevel::Info("metric stream timed out"));
+                ctx.stop();
+            } else {
+                ctx.ping(b"");
+            }
+        });
+    }
+}
+
+impl StreamHandler<Update> for MetricStream {
+    fn handle(&mut self, update: Update, ctx: &mut Self::Context) {
+        match update {
+            Update::Snapshot(snapshot) => {
+                for event in self.subscriptions.events(&snapshot) {
+                    self.reply(Reply::Update(event), ctx);
+                }
+            }
+            Update::Lagged(skipped) => self.reply(Reply::Lagged { skipped }, ctx),
+        }
+    }
+}
+
+impl StreamHandler<Result<Message, ProtocolError>> for MetricStream {
+    fn handle(&mut self, item: Result<Message, ProtocolError>, ctx: &mut Self::Context) {
+        self.heartbeat = Instant::now();
+        match item {
+            Ok(Message::Text(text)) => self.request(&text, ctx),
+            Ok(Message::Binary(_)) => self.reply(
+                Reply::Error {
+                    info: "requests are JSON text".to_string(),
+                },
+                ctx,
+            ),
+            Ok(Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log(LogLevel::Warn(
+                    std::format!("metric stream protocol error: {}", e).as_str(),
+                ));
+                ctx.stop();
+            }
+        }
+    }
+}
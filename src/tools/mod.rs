@@ -5,8 +5,10 @@ mod interpreter;
 mod logger;
 mod os_type;
 mod proc;
+mod size_format;
 pub use file_type::file_content_type;
 pub use interpreter::*;
 pub use logger::log::*;
 pub use os_type::*;
 pub use proc::*;
+pub use size_format::*;
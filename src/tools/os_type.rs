@@ -1,32 +1,278 @@
-use serde::{Deserialize, Serialize};
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub enum BitType {
-    B(u64),
-    KB(u64),
-    MB(u64),
-    GB(u64),
-    TB(u64),
-    PB(u64),
-    YB(u64),
-    Unknown(u64),
+use std::{
+    convert::TryFrom,
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::tools::size_format::BIT_TYPE;
+
+/// How a `BitType` is written by serde, picked per request with
+/// `with_size_format`, in bytes without it.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeFormat {
+    // a number of bytes
+    #[default]
+    Bytes,
+    // "15.5 GiB", powers of 1024 as free -h
+    Iec,
+    // "16.7 GB", powers of 1000 as df -H
+    Si,
+}
+
+/// A quantity of bytes.  Sums and differences saturate, as the counters
+/// they come from can only be compared, not go negative.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BitType(u64);
+
+// what `format` writes and `FromStr` reads back, powers of 1024 and 1000
+const IEC_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const SI_UNITS: [&str; 6] = ["kB", "MB", "GB", "TB", "PB", "EB"];
+
+// the kernel means KiB when it writes kB, and so does cpuinfo with KB
+fn kernel_multiplier(unit: &str) -> Option<u64> {
+    Some(match unit {
+        "B" => 1,
+        "k" | "K" | "kB" | "KB" | "KiB" => 1 << 10,
+        "M" | "MB" | "MiB" => 1 << 20,
+        "G" | "GB" | "GiB" => 1 << 30,
+        "T" | "TB" | "TiB" => 1 << 40,
+        "P" | "PB" | "PiB" => 1 << 50,
+        "E" | "EB" | "EiB" => 1 << 60,
+        "page" | "pages" => BitType::page_size(),
+        _ => return None,
+    })
+}
+
+fn written_multiplier(unit: &str) -> Option<u64> {
+    if unit == "B" {
+        return Some(1);
+    }
+    let power = |units: &[&str; 6]| units.iter().position(|u| *u == unit).map(|p| p as u32 + 1);
+    match (power(&IEC_UNITS), power(&SI_UNITS)) {
+        (Some(power), _) => Some(1024u64.pow(power)),
+        (_, Some(power)) => Some(1000u64.pow(power)),
+        _ => None,
+    }
+}
+
+// "15.5 GiB" or "2048 kB" with `multiplier` telling what the unit means,
+// a number alone is in bytes
+fn scaled(s: &str, multiplier: fn(&str) -> Option<u64>) -> Result<BitType, String> {
+    let mut fields = s.split_whitespace();
+    let size = fields.next().unwrap_or_default();
+    let multiplier = match fields.next() {
+        Some(unit) => multiplier(unit).ok_or_else(|| std::format!("{:?}: unknown unit", unit))?,
+        None => 1,
+    };
+    let bytes = match size.parse::<u64>() {
+        Ok(size) => size.checked_mul(multiplier),
+        Err(e) => match size.parse::<f64>().map(|size| size * multiplier as f64) {
+            Ok(bytes) if bytes >= 0.0 && bytes < u64::MAX as f64 => Some(bytes.round() as u64),
+            Ok(bytes) if bytes >= 0.0 => None,
+            _ => return Err(std::format!("{:?}: {}", size, e)),
+        },
+    };
+    bytes
+        .map(BitType)
+        .ok_or_else(|| std::format!("{:?}: too large", s))
 }
 
 impl BitType {
-    pub fn new(s: &str) -> Option<BitType> {
-        let mut name_value = s.split(" ");
-        let size = match name_value.next().unwrap().parse::<u64>() {
-            Ok(v) => v,
-            Err(_) => panic!("value is out of unsigned long!"),
+    pub const fn from_bytes(bytes: u64) -> BitType {
+        BitType(bytes)
+    }
+
+    /// `None` when it does not fit.
+    pub fn from_pages(pages: u64) -> Option<BitType> {
+        pages.checked_mul(BitType::page_size()).map(BitType)
+    }
+
+    /// The unit of statm and of the page counts of vmstat and zoneinfo.
+    pub fn page_size() -> u64 {
+        match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            n if n > 0 => n as u64,
+            _ => 4096,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+
+    /// A size as procfs and sysfs write it, e.g. "2048 kB", "12 pages" or
+    /// memory.current in bytes.  Only for the readers of those files, kB
+    /// is 1024 bytes there while `FromStr` reads it as 1000.
+    pub(crate) fn from_kernel(s: &str) -> Result<BitType, String> {
+        scaled(s, kernel_multiplier)
+    }
+
+    /// e.g. "15.5 GiB" or "16.7 GB", bytes below one kilo are kept whole.
+    pub fn format(&self, format: SizeFormat) -> String {
+        let (base, units) = match format {
+            SizeFormat::Bytes => return std::format!("{} B", self.0),
+            SizeFormat::Iec => (1024.0, IEC_UNITS),
+            SizeFormat::Si => (1000.0, SI_UNITS),
         };
-        Some(match name_value.next()?.to_ascii_uppercase().as_str() {
-            "B" => BitType::B(size),
-            "KB" => BitType::KB(size),
-            "MB" => BitType::MB(size),
-            "GB" => BitType::GB(size),
-            "TB" => BitType::TB(size),
-            "PB" => BitType::PB(size),
-            "YB" => BitType::YB(size),
-            _ => BitType::Unknown(size),
-        })
+        let mut value = self.0 as f64;
+        if value < base {
+            return std::format!("{} B", self.0);
+        }
+        let mut unit = units[0];
+        for next in units.iter() {
+            value /= base;
+            unit = next;
+            if value < base {
+                break;
+            }
+        }
+        std::format!("{:.1} {}", value, unit)
+    }
+}
+
+impl fmt::Display for BitType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.format(SizeFormat::Iec).as_str())
+    }
+}
+
+/// Reads back what `format` writes, "2.0 MiB" or "2.0 MB", which is
+/// 2000000 bytes.  A number alone is in bytes.
+impl FromStr for BitType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        scaled(s, written_multiplier)
+    }
+}
+
+impl Add for BitType {
+    type Output = BitType;
+
+    fn add(self, other: BitType) -> BitType {
+        BitType(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for BitType {
+    fn add_assign(&mut self, other: BitType) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for BitType {
+    type Output = BitType;
+
+    fn sub(self, other: BitType) -> BitType {
+        BitType(self.0.saturating_sub(other.0))
+    }
+}
+
+impl SubAssign for BitType {
+    fn sub_assign(&mut self, other: BitType) {
+        *self = *self - other;
     }
 }
+
+impl Sum for BitType {
+    fn sum<I: Iterator<Item = BitType>>(iter: I) -> BitType {
+        iter.fold(BitType::default(), Add::add)
+    }
+}
+
+// a number of bytes, unless `with_size_format` asks for another format
+impl Serialize for BitType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(BIT_TYPE, &self.0)
+    }
+}
+
+struct BitTypeVisitor;
+
+impl<'de> de::Visitor<'de> for BitTypeVisitor {
+    type Value = BitType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number of bytes or a size such as \"2.0 MiB\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<BitType, E> {
+        Ok(BitType(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<BitType, E> {
+        u64::try_from(v).map(BitType).map_err(E::custom)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<BitType, E> {
+        v.parse::<BitType>().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for BitType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(BitTypeVisitor)
+    }
+}
+
+#[test]
+fn bit_type_test() {
+    use crate::tools::with_size_format;
+
+    let kib = BitType::from_kernel("2048 kB").unwrap();
+    assert_eq!(kib, BitType::from_kernel("2 MB").unwrap());
+    assert!(BitType::from_kernel("1 GiB").unwrap() > kib);
+    assert_eq!(BitType::from_kernel("12288 KB").unwrap().bytes(), 12 << 20);
+    assert_eq!(
+        BitType::from_kernel("3 pages").unwrap().bytes(),
+        3 * BitType::page_size()
+    );
+    assert_eq!(BitType::from_kernel("512"), Ok(BitType::from_bytes(512)));
+    assert!(BitType::from_kernel("many").is_err());
+    assert!(BitType::from_kernel("1 parsecs").is_err());
+    assert!(BitType::from_kernel("99999999999 EB").is_err());
+    assert_eq!("536870912".parse::<BitType>().unwrap().bytes(), 512 << 20);
+    // the written form has no kernel units
+    assert_eq!("2 MB".parse::<BitType>().unwrap().bytes(), 2_000_000);
+    assert!("2048 KB".parse::<BitType>().is_err());
+    assert!("3 pages".parse::<BitType>().is_err());
+
+    let total = BitType::from_bytes(16_655_601_664);
+    assert_eq!(total.format(SizeFormat::Iec), "15.5 GiB");
+    assert_eq!(total.format(SizeFormat::Si), "16.7 GB");
+    assert_eq!(BitType::from_bytes(1000).format(SizeFormat::Iec), "1000 B");
+    assert_eq!(BitType::from_bytes(1000).format(SizeFormat::Si), "1.0 kB");
+    assert_eq!(
+        total.format(SizeFormat::Iec).parse::<BitType>().unwrap(),
+        BitType::from_bytes(16_642_998_272)
+    );
+    assert_eq!(kib + kib - total, BitType::default());
+    assert_eq!(vec![kib, kib].into_iter().sum::<BitType>().bytes(), 4 << 20);
+
+    assert_eq!(serde_json::to_string(&kib).unwrap(), "2097152");
+    let human = serde_json::to_string(&with_size_format(&kib, SizeFormat::Iec)).unwrap();
+    assert_eq!(human, r#""2.0 MiB""#);
+    assert_eq!(
+        serde_json::from_str::<BitType>(human.as_str()).unwrap(),
+        kib
+    );
+    assert_eq!(serde_json::from_str::<BitType>("2097152").unwrap(), kib);
+
+    // bytes to ?units=si and back
+    let exact = BitType::from_bytes(2_000_000);
+    let si = serde_json::to_string(&with_size_format(&exact, SizeFormat::Si)).unwrap();
+    assert_eq!(si, r#""2.0 MB""#);
+    assert_eq!(serde_json::from_str::<BitType>(si.as_str()).unwrap(), exact);
+    assert_eq!(
+        total
+            .format(SizeFormat::Si)
+            .parse::<BitType>()
+            .unwrap()
+            .bytes(),
+        16_700_000_000
+    );
+}
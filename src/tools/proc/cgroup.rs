@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    sysfs::{read_size, read_value},
+    BitType, SysRoot,
+};
+
+/// cpu.stat, times in microseconds.  The throttling fields only exist
+/// with the cpu controller enabled.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CgroupCpu {
+    usage_usec: Option<u64>,
+    user_usec: Option<u64>,
+    system_usec: Option<u64>,
+    nr_periods: Option<u64>,
+    nr_throttled: Option<u64>,
+    throttled_usec: Option<u64>,
+}
+
+/// One line of io.stat, the counters of one block device.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CgroupIo {
+    // "major:minor"
+    device: String,
+    rbytes: Option<u64>,
+    wbytes: Option<u64>,
+    rios: Option<u64>,
+    wios: Option<u64>,
+    dbytes: Option<u64>,
+    dios: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Cgroup {
+    /*
+       Path below the hierarchy root, "/" for the root itself, the same
+       as the "0::" line of /proc/[pid]/cgroup.
+    */
+    path: String,
+    cpu: CgroupCpu,
+    // memory.current, missing on the root
+    memory_current: Option<BitType>,
+    // memory.max, None when it is "max"
+    memory_max: Option<BitType>,
+    io: Vec<CgroupIo>,
+    // pids.current, tasks including threads
+    pids_current: Option<u64>,
+    children: Vec<Cgroup>,
+}
+
+/// Usage of a cgroup between two readings.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CgroupRate {
+    path: String,
+    // percent of one processor, can exceed 100 on several
+    cpu: f64,
+    // per second, summed over every device
+    read_bytes: f64,
+    write_bytes: f64,
+    read_iops: f64,
+    write_iops: f64,
+}
+
+/// Where the cgroup v2 hierarchy is mounted, fs/cgroup or, on hybrid
+/// setups with cgroup v1 controllers there, fs/cgroup/unified.
+pub(crate) fn unified_root(sys: &SysRoot) -> Option<PathBuf> {
+    ["fs/cgroup", "fs/cgroup/unified"]
+        .iter()
+        .map(|path| sys.join(path))
+        .find(|path| path.join("cgroup.controllers").exists())
+}
+
+// "usage_usec 1160070713"
+fn convert_cpu(source: &str) -> CgroupCpu {
+    let mut cpu = CgroupCpu::default();
+    for line in source.split('\n') {
+        let mut fields = line.split_whitespace();
+        let (name, value) = match (fields.next(), fields.next()) {
+            (Some(name), Some(value)) => (name, value.parse::<u64>().ok()),
+            _ => continue,
+        };
+        match name {
+            "usage_usec" => cpu.usage_usec = value,
+            "user_usec" => cpu.user_usec = value,
+            "system_usec" => cpu.system_usec = value,
+            "nr_periods" => cpu.nr_periods = value,
+            "nr_throttled" => cpu.nr_throttled = value,
+            "throttled_usec" => cpu.throttled_usec = value,
+            _ => {}
+        }
+    }
+    cpu
+}
+
+// "8:16 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0"
+fn convert_io(source: &str) -> Vec<CgroupIo> {
+    source
+        .split('\n')
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mut io = CgroupIo {
+                device: fields.next()?.to_string(),
+                ..CgroupIo::default()
+            };
+            for (name, value) in fields.filter_map(|field| field.split_once('=')) {
+                let value = value.parse::<u64>().ok();
+                match name {
+                    "rbytes" => io.rbytes = value,
+                    "wbytes" => io.wbytes = value,
+                    "rios" => io.rios = value,
+                    "wios" => io.wios = value,
+                    "dbytes" => io.dbytes = value,
+                    "dios" => io.dios = value,
+                    _ => {}
+                }
+            }
+            Some(io)
+        })
+        .collect()
+}
+
+fn read_cgroup(directory: &Path, path: String) -> Cgroup {
+    let mut children = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                // controller files share the directory with the children
+                if !entry.file_type().ok()?.is_dir() {
+                    return None;
+                }
+                let name = entry.file_name().to_str()?.to_string();
+                let child = match path.as_str() {
+                    "/" => std::format!("/{}", name),
+                    _ => std::format!("{}/{}", path, name),
+                };
+                Some(read_cgroup(&entry.path(), child))
+            })
+            .collect::<Vec<Cgroup>>(),
+        // removed while walking
+        Err(_) => Vec::new(),
+    };
+    children.sort_by(|a, b| a.path.cmp(&b.path));
+    Cgroup {
+        cpu: std::fs::read_to_string(directory.join("cpu.stat"))
+            .map(|s| convert_cpu(s.as_str()))
+            .unwrap_or_default(),
+        memory_current: read_size(&directory.join("memory.current")),
+        memory_max: read_size(&directory.join("memory.max")),
+        io: std::fs::read_to_string(directory.join("io.stat"))
+            .map(|s| convert_io(s.as_str()))
+            .unwrap_or_default(),
+        pids_current: read_value(&directory.join("pids.current")),
+        children,
+        path,
+    }
+}
+
+/// The whole cgroup v2 tree, None without a v2 hierarchy.
+pub fn cgroups(sys: &SysRoot) -> std::io::Result<Option<Cgroup>> {
+    Ok(unified_root(sys).map(|root| read_cgroup(&root, "/".to_string())))
+}
+
+/// The cgroup v2 path of a process from /proc/[pid]/cgroup, the "0::" line.
+pub(crate) fn process_cgroup(source: &str) -> Option<String> {
+    source
+        .split('\n')
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| path.trim().to_string())
+}
+
+fn flatten<'a>(cgroup: &'a Cgroup, all: &mut HashMap<&'a str, &'a Cgroup>) {
+    all.insert(cgroup.path.as_str(), cgroup);
+    for child in cgroup.children.iter() {
+        flatten(child, all);
+    }
+}
+
+#[inline]
+fn delta(previous: Option<u64>, current: Option<u64>) -> u64 {
+    current.unwrap_or(0).saturating_sub(previous.unwrap_or(0))
+}
+
+/// Rates between two `cgroups()` readings taken `seconds` apart, for the
+/// cgroups present in both, in tree order.
+pub fn cgroup_rates(previous: &Cgroup, current: &Cgroup, seconds: f64) -> Vec<CgroupRate> {
+    if seconds <= 0.0 {
+        return Vec::new();
+    }
+    let mut before = HashMap::new();
+    flatten(previous, &mut before);
+    let mut rates = Vec::new();
+    let mut stack = vec![current];
+    while let Some(c) = stack.pop() {
+        stack.extend(c.children.iter().rev());
+        let p = match before.get(c.path.as_str()) {
+            Some(p) => p,
+            None => continue,
+        };
+        let io = |field: fn(&CgroupIo) -> Option<u64>| {
+            c.io.iter()
+                .filter_map(|io| {
+                    let previous = p.io.iter().find(|p| p.device == io.device)?;
+                    Some(delta(field(previous), field(io)))
+                })
+                .sum::<u64>() as f64
+                / seconds
+        };
+        rates.push(CgroupRate {
+            path: c.path.clone(),
+            cpu: delta(p.cpu.usage_usec, c.cpu.usage_usec) as f64 / (seconds * 10_000.0),
+            read_bytes: io(|io| io.rbytes),
+            write_bytes: io(|io| io.wbytes),
+            read_iops: io(|io| io.rios),
+            write_iops: io(|io| io.wios),
+        });
+    }
+    rates
+}
+
+#[test]
+fn cgroup_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let root = cgroups(&SysRoot::new(fixtures.join("sys")))
+        .unwrap()
+        .unwrap();
+    assert_eq!(root.path, "/");
+    assert!(root.memory_current.is_none());
+    assert_eq!(
+        root.children
+            .iter()
+            .map(|c| c.path.as_str())
+            .collect::<Vec<_>>(),
+        ["/system.slice", "/user.slice"]
+    );
+    let system = &root.children[0];
+    assert_eq!(
+        system.memory_current,
+        Some(BitType::from_bytes(1_238_056_960))
+    );
+    // "max"
+    assert!(system.memory_max.is_none());
+    let sshd = &system.children[0];
+    assert_eq!(sshd.path, "/system.slice/sshd.service");
+    assert_eq!(sshd.memory_max, Some(BitType::from_bytes(536_870_912)));
+    assert_eq!(sshd.pids_current, Some(3));
+    assert_eq!(sshd.cpu.usage_usec, Some(4_125_003));
+    assert_eq!(sshd.io[0].device, "259:0");
+    assert_eq!(sshd.io[0].wbytes, Some(1_052_672));
+    assert!(cgroups(&SysRoot::new(fixtures.join("missing")))
+        .unwrap()
+        .is_none());
+
+    let mut later = root.clone();
+    later.children[0].children[0].cpu.usage_usec = Some(4_125_003 + 500_000);
+    later.children[0].children[0].io[0].rbytes = Some(8192 + 4096);
+    let rates = cgroup_rates(&root, &later, 2.0);
+    assert_eq!(rates.len(), 4);
+    let sshd = rates
+        .iter()
+        .find(|r| r.path == "/system.slice/sshd.service")
+        .unwrap();
+    assert_eq!((sshd.cpu, sshd.read_bytes), (25.0, 2048.0));
+    assert_eq!(
+        process_cgroup("1:name=systemd:/\n0::/user.slice/user-1000.slice\n").as_deref(),
+        Some("/user.slice/user-1000.slice")
+    );
+}
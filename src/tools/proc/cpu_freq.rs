@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    sysfs::{numbered, read_line, read_value, read_words},
+    SysRoot,
+};
+
+/// A cpufreq policy, the processors that share one clock and governor.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CpuFreqPolicy {
+    // the N of policyN
+    pub(crate) id: u32,
+    // affected_cpus, the online processors of the policy
+    cpus: Vec<u32>,
+    /*
+       Frequencies in kHz.  cur_freq, min_freq and max_freq are the
+       scaling_* ones, the range the governor may use, while
+       cpuinfo_min_freq and cpuinfo_max_freq are what the hardware can do.
+    */
+    pub(crate) cur_freq: Option<u64>,
+    min_freq: Option<u64>,
+    max_freq: Option<u64>,
+    cpuinfo_min_freq: Option<u64>,
+    cpuinfo_max_freq: Option<u64>,
+    // scaling_governor, e.g. "powersave"
+    governor: Option<String>,
+    // scaling_available_governors
+    available_governors: Vec<String>,
+    // scaling_driver, e.g. "intel_pstate"
+    driver: Option<String>,
+}
+
+fn read_policy(directory: &Path, id: u32) -> CpuFreqPolicy {
+    let khz = |name: &str| read_value::<u64>(&directory.join(name));
+    CpuFreqPolicy {
+        id,
+        cpus: read_words(&directory.join("affected_cpus"))
+            .iter()
+            .filter_map(|cpu| cpu.parse::<u32>().ok())
+            .collect(),
+        // cpuinfo_cur_freq is only readable by root on most drivers
+        cur_freq: khz("scaling_cur_freq").or_else(|| khz("cpuinfo_cur_freq")),
+        min_freq: khz("scaling_min_freq"),
+        max_freq: khz("scaling_max_freq"),
+        cpuinfo_min_freq: khz("cpuinfo_min_freq"),
+        cpuinfo_max_freq: khz("cpuinfo_max_freq"),
+        governor: read_line(&directory.join("scaling_governor")),
+        available_governors: read_words(&directory.join("scaling_available_governors")),
+        driver: read_line(&directory.join("scaling_driver")),
+    }
+}
+
+/// Every cpufreq policy, none on machines without frequency scaling, such
+/// as most virtual ones.
+pub fn cpu_freq(sys: &SysRoot) -> std::io::Result<Vec<CpuFreqPolicy>> {
+    let cpufreq = sys.join("devices/system/cpu/cpufreq");
+    let ids = match numbered(&cpufreq, "policy", "") {
+        Ok(ids) => ids,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(ids
+        .into_iter()
+        .map(|id| read_policy(&cpufreq.join(std::format!("policy{}", id)), id))
+        .collect())
+}
+
+/// Switch the governor of a policy, which needs write access to sysfs.
+/// Only the governors the policy lists as available are accepted.
+pub fn set_governor(sys: &SysRoot, policy: u32, governor: &str) -> std::io::Result<()> {
+    let directory = sys.join(std::format!("devices/system/cpu/cpufreq/policy{}", policy));
+    if !directory.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            std::format!("no cpufreq policy{}", policy),
+        ));
+    }
+    let available = read_words(&directory.join("scaling_available_governors"));
+    if !available.iter().any(|g| g == governor) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            std::format!("governor {:?} is not one of {:?}", governor, available),
+        ));
+    }
+    std::fs::write(directory.join("scaling_governor"), governor)
+}
+
+#[test]
+fn cpu_freq_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let policies = cpu_freq(&SysRoot::new(fixtures.join("sys"))).unwrap();
+    assert_eq!(policies.iter().map(|p| p.id).collect::<Vec<_>>(), [0, 2]);
+    let policy = &policies[1];
+    assert_eq!(policy.cpus, [2, 3]);
+    assert_eq!(
+        (policy.cur_freq, policy.min_freq, policy.max_freq),
+        (Some(2_400_000), Some(800_000), Some(4_200_000))
+    );
+    assert_eq!(policy.governor.as_deref(), Some("powersave"));
+    assert_eq!(policy.available_governors, ["performance", "powersave"]);
+    // no scaling_cur_freq on policy0
+    assert_eq!(policies[0].cur_freq, Some(1_800_000));
+    assert!(cpu_freq(&SysRoot::new(fixtures.join("missing")))
+        .unwrap()
+        .is_empty());
+
+    // set_governor writes, so it works on a copy
+    let sys = std::env::temp_dir().join(std::format!("serverd-cpu-freq-{}", std::process::id()));
+    let policy2 = sys.join("devices/system/cpu/cpufreq/policy2");
+    std::fs::create_dir_all(&policy2).unwrap();
+    for name in ["scaling_governor", "scaling_available_governors"].iter() {
+        std::fs::copy(
+            fixtures
+                .join("sys/devices/system/cpu/cpufreq/policy2")
+                .join(name),
+            policy2.join(name),
+        )
+        .unwrap();
+    }
+    let sys_root = SysRoot::new(&sys);
+    assert_eq!(
+        set_governor(&sys_root, 2, "ondemand").unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        set_governor(&sys_root, 5, "performance")
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::NotFound
+    );
+    set_governor(&sys_root, 2, "performance").unwrap();
+    assert_eq!(
+        read_line(&policy2.join("scaling_governor")).as_deref(),
+        Some("performance")
+    );
+    std::fs::remove_dir_all(&sys).unwrap();
+}
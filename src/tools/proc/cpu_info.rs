@@ -1,14 +1,23 @@
+use std::{collections::BTreeMap, convert::TryFrom};
+
 use serde::{Deserialize, Serialize};
 
 use crate::tools::{
-    logger::log::{log, LogLevel},
-    BitType,
+    parse::{number, ParseMode, Parsed, Parser, ProcParseError},
+    BitType, ProcRoot, SysRoot,
 };
 
 // #[derive(Debug)]
 // pub enum CpuInfoField {
 //     processor(String),
 // }
+/*
+   Every architecture writes fields of its own, those below are
+   the ones of x86, aarch64, riscv64 and ppc64le.  What describes
+   the whole machine, such as Hardware on ARM boards or platform on
+   POWER, comes in a block of its own and is copied to every
+   processor.
+*/
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct CpuInfo {
     processor: Option<u32>,
@@ -38,100 +47,278 @@ pub struct CpuInfo {
     cache_alignment: Option<u32>,
     address_sizes: Option<String>,
     power_management: Option<String>,
+    // aarch64, the fields of MIDR_EL1, e.g. implementer 0x41 is Arm
+    cpu_implementer: Option<u32>,
+    cpu_architecture: Option<u32>,
+    cpu_variant: Option<u32>,
+    cpu_part: Option<u32>,
+    cpu_revision: Option<u32>,
+    features: Option<Vec<String>>,
+    // riscv64, the hart id and ISA string, e.g. rv64imafdc
+    hart: Option<u32>,
+    isa: Option<String>,
+    mmu: Option<String>,
+    uarch: Option<String>,
+    mvendorid: Option<u64>,
+    marchid: Option<u64>,
+    mimpid: Option<u64>,
+    // ppc64le, e.g. "POWER9 (raw), altivec supported"
+    cpu: Option<String>,
+    revision: Option<String>,
+    timebase: Option<u64>,
+    platform: Option<String>,
+    machine: Option<String>,
+    firmware: Option<String>,
+    // machine wide on ARM boards: the SoC, serial number and model
+    hardware: Option<String>,
+    serial: Option<String>,
+    board_model: Option<String>,
+    // fields not known above, as written
+    extra: BTreeMap<String, String>,
 }
 
 #[inline]
-fn read_cpu_info(cpu_info_file_path: &str) -> std::io::Result<String> {
-    std::fs::read_to_string(cpu_info_file_path)
+fn words(value: &str) -> Option<Vec<String>> {
+    Some(value.split(' ').map(|s| s.to_string()).collect())
 }
 
 #[inline]
-fn all_core_info_collect(cpu_info_string: String) -> Vec<String> {
-    cpu_info_string
-        .split("\n\n")
-        .map(|s| s.to_string())
-        .collect()
+fn hex<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16)
+        .ok()
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| std::format!("{:?}: not a hex number in range", value))
 }
 
-#[inline]
-fn each_core_info_struct(core_info: String) -> CpuInfo {
-    let core_detail = core_info
-        .split('\n')
-        .map(|s| -> Option<(String, String)> {
-            let mut name_value_iter = s.split(':').into_iter().map(|s| s.trim().to_string());
-            let name = name_value_iter.next()?;
-            let value = name_value_iter.next()?;
-            Some((name, value))
-        })
-        .flatten()
-        .collect::<Vec<(String, String)>>();
-    let mut cpu_info: CpuInfo = CpuInfo::default();
-    for (name, value) in core_detail {
-        let match_with = name.to_ascii_lowercase().replace(" ", "_");
-        match match_with.as_str() {
-            // TODO: simplify this! (macro rule?)
-            // u32
-            "model" => cpu_info.model = Some(value.parse::<u32>().unwrap()),
-            "apicid" => cpu_info.apicid = Some(value.parse::<u32>().unwrap()),
-            "core_id" => cpu_info.core_id = Some(value.parse::<u32>().unwrap()),
-            "stepping" => cpu_info.stepping = Some(value.parse::<u32>().unwrap()),
-            "siblings" => cpu_info.siblings = Some(value.parse::<u32>().unwrap()),
-            "processor" => cpu_info.processor = Some(value.parse::<u32>().unwrap()),
-            "cpu_cores" => cpu_info.cpu_cores = Some(value.parse::<u32>().unwrap()),
-            "cpu_family" => cpu_info.cpu_family = Some(value.parse::<u32>().unwrap()),
-            "physical_id" => cpu_info.physical_id = Some(value.parse::<u32>().unwrap()),
-            "cpuid_level" => cpu_info.cpuid_level = Some(value.parse::<u32>().unwrap()),
-            "clflush_size" => cpu_info.clflush_size = Some(value.parse::<u32>().unwrap()),
-            "initial_apicid" => cpu_info.initial_apicid = Some(value.parse::<u32>().unwrap()),
-            "cache_alignment" => cpu_info.cache_alignment = Some(value.parse::<u32>().unwrap()),
-            // hex number
-            "microcode" => {
-                cpu_info.microcode =
-                    Some(u32::from_str_radix(value.trim_start_matches("0x"), 16).unwrap())
+// one "name : value" line of a core, the reason when it is not understood
+fn fill_field(cpu_info: &mut CpuInfo, name: &str, value: &str) -> Result<(), String> {
+    let match_with = name.to_ascii_lowercase().replace(' ', "_");
+    match match_with.as_str() {
+        // u32, on ARM boards and POWER the name of the machine instead
+        "model" => match value.parse::<u32>() {
+            Ok(model) => cpu_info.model = Some(model),
+            Err(_) => cpu_info.board_model = Some(value.to_string()),
+        },
+        "apicid" => cpu_info.apicid = Some(number(value)?),
+        "core_id" => cpu_info.core_id = Some(number(value)?),
+        "stepping" => cpu_info.stepping = Some(number(value)?),
+        "siblings" => cpu_info.siblings = Some(number(value)?),
+        "processor" => cpu_info.processor = Some(number(value)?),
+        "cpu_cores" => cpu_info.cpu_cores = Some(number(value)?),
+        "cpu_family" => cpu_info.cpu_family = Some(number(value)?),
+        "physical_id" => cpu_info.physical_id = Some(number(value)?),
+        "cpuid_level" => cpu_info.cpuid_level = Some(number(value)?),
+        "clflush_size" => cpu_info.clflush_size = Some(number(value)?),
+        "initial_apicid" => cpu_info.initial_apicid = Some(number(value)?),
+        "cache_alignment" => cpu_info.cache_alignment = Some(number(value)?),
+        "cpu_architecture" => cpu_info.cpu_architecture = Some(number(value)?),
+        "cpu_revision" => cpu_info.cpu_revision = Some(number(value)?),
+        "hart" => cpu_info.hart = Some(number(value)?),
+        // u64
+        "timebase" => cpu_info.timebase = Some(number(value)?),
+        // hex number
+        "microcode" => cpu_info.microcode = Some(hex(value)?),
+        "cpu_implementer" => cpu_info.cpu_implementer = Some(hex(value)?),
+        "cpu_variant" => cpu_info.cpu_variant = Some(hex(value)?),
+        "cpu_part" => cpu_info.cpu_part = Some(hex(value)?),
+        "mvendorid" => cpu_info.mvendorid = Some(hex(value)?),
+        "marchid" => cpu_info.marchid = Some(hex(value)?),
+        "mimpid" => cpu_info.mimpid = Some(hex(value)?),
+        // String
+        "vendor_id" => cpu_info.vendor_id = Some(value.to_string()),
+        "model_name" => cpu_info.model_name = Some(value.to_string()),
+        "address_sizes" => cpu_info.address_sizes = Some(value.to_string()),
+        "isa" => cpu_info.isa = Some(value.to_string()),
+        "mmu" => cpu_info.mmu = Some(value.to_string()),
+        "uarch" => cpu_info.uarch = Some(value.to_string()),
+        "cpu" => cpu_info.cpu = Some(value.to_string()),
+        "revision" => cpu_info.revision = Some(value.to_string()),
+        "platform" => cpu_info.platform = Some(value.to_string()),
+        "machine" => cpu_info.machine = Some(value.to_string()),
+        "firmware" => cpu_info.firmware = Some(value.to_string()),
+        "hardware" => cpu_info.hardware = Some(value.to_string()),
+        "serial" => cpu_info.serial = Some(value.to_string()),
+        // BitInfo
+        "cache_size" => cpu_info.cache_size = Some(BitType::from_kernel(value)?),
+        // f32
+        "cpu_mhz" => cpu_info.cpu_mhz = Some(number(value)?),
+        // ppc64le, e.g. "2166.000000MHz"
+        "clock" => cpu_info.cpu_mhz = Some(number(value.trim_end_matches("MHz"))?),
+        // f64
+        "bogomips" => cpu_info.bogomips = Some(number(value)?),
+        // bool
+        "fpu" => cpu_info.fpu = Some(value == "yes"),
+        "fpu_exception" => cpu_info.fpu_exception = Some(value == "yes"),
+        "wp" => cpu_info.wp = Some(value == "yes"),
+        // Vec String
+        "flags" => cpu_info.flags = words(value),
+        "vmx_flags" => cpu_info.vmx_flags = words(value),
+        "bugs" => cpu_info.bugs = words(value),
+        "features" => cpu_info.features = words(value),
+        "power_management" => cpu_info.power_management = Some(value.to_string()),
+        _ => {
+            cpu_info.extra.insert(name.to_string(), value.to_string());
+        }
+    }
+    Ok(())
+}
+
+// "name : value" lines and the line number of each
+type Block<'a> = Vec<(usize, &'a str, &'a str)>;
+
+// blocks are separated by an empty line, one per processor and maybe one
+// for the machine
+fn convert(source: &str, parser: &mut Parser) -> Result<Vec<CpuInfo>, ProcParseError> {
+    let mut blocks: Vec<Block> = vec![Vec::new()];
+    for (index, line) in source.split('\n').enumerate() {
+        if line.trim().is_empty() {
+            blocks.push(Vec::new());
+            continue;
+        }
+        match line.split_once(':') {
+            Some((name, value)) => {
+                if let Some(block) = blocks.last_mut() {
+                    block.push((index + 1, name.trim(), value.trim()));
+                }
             }
-            // String
-            "vendor_id" => cpu_info.vendor_id = Some(value),
-            "model_name" => cpu_info.model_name = Some(value),
-            "address_sizes" => cpu_info.address_sizes = Some(value),
-            // BitInfo
-            "cache_size" => cpu_info.cache_size = BitType::new(value.as_str()),
-            // f32
-            "cpu_mhz" => cpu_info.cpu_mhz = Some(value.parse::<f32>().unwrap()),
-            // f64
-            "bogomips" => cpu_info.bogomips = Some(value.parse::<f64>().unwrap()),
-            // bool
-            "fpu" => cpu_info.fpu = Some(value == "yes"),
-            "fpu_exception" => cpu_info.fpu_exception = Some(value == "yes"),
-            "wp" => cpu_info.wp = Some(value == "yes"),
-            // Vec String
-            "flags" => cpu_info.flags = Some(value.split(' ').map(|s| s.to_string()).collect()),
-            "vmx_flags" => {
-                cpu_info.vmx_flags = Some(value.split(' ').map(|s| s.to_string()).collect())
+            None => parser.problem(index + 1, line.trim(), "no ':' in line".to_string())?,
+        }
+    }
+    let (processors, machine): (Vec<Block>, Vec<Block>) = blocks
+        .into_iter()
+        .filter(|block| !block.is_empty())
+        .partition(|block| block.iter().any(|(_, name, _)| *name == "processor"));
+    let mut cores = Vec::new();
+    for block in processors.iter() {
+        let mut core = CpuInfo::default();
+        for (line, name, value) in block.iter() {
+            if let Err(reason) = fill_field(&mut core, name, value) {
+                parser.problem(*line, name, reason)?;
             }
-            "bugs" => cpu_info.bugs = Some(value.split(' ').map(|s| s.to_string()).collect()),
-            // _ => print!("[warn] !{} not support yet!", &name),
-            "power_management" => cpu_info.power_management = Some(value),
-            _ => log(LogLevel::Warn(
-                std::format!("{} not support yet!", &name).as_str(),
-            )),
         }
+        cores.push(core);
     }
-    cpu_info
+    for (line, name, value) in machine.concat() {
+        let mut reasons = cores
+            .iter_mut()
+            .filter_map(|core| fill_field(core, name, value).err());
+        // the same for every core, report it once
+        if let Some(reason) = reasons.next() {
+            parser.problem(line, name, reason)?;
+        }
+    }
+    Ok(cores)
 }
 
-pub fn cpu_info() -> std::io::Result<Vec<CpuInfo>> {
-    let cpu_info_origin = read_cpu_info("/proc/cpuinfo")?;
-    let split_with_each_core_info = all_core_info_collect(cpu_info_origin);
-    Ok(split_with_each_core_info
+// cpufreq counts in kHz, the governor's view first, then the hardware's
+fn sysfs_mhz(sys: &SysRoot, processor: u32) -> Option<f32> {
+    let cpufreq = sys.join(std::format!("devices/system/cpu/cpu{}/cpufreq", processor));
+    ["scaling_cur_freq", "cpuinfo_cur_freq"]
         .iter()
-        .filter(|s| !s.is_empty())
-        .map(|s| -> CpuInfo { each_core_info_struct(s.clone()) })
-        .collect())
+        .find_map(|name| {
+            std::fs::read_to_string(cpufreq.join(name))
+                .ok()?
+                .trim()
+                .parse::<u32>()
+                .ok()
+        })
+        .map(|khz| khz as f32 / 1000.0)
+}
+
+/// One entry per processor.  Where cpuinfo has no frequency, as on most
+/// ARM and RISC-V machines, it is read from cpufreq in sysfs.
+pub fn cpu_info(
+    proc: &ProcRoot,
+    sys: &SysRoot,
+    mode: ParseMode,
+) -> std::io::Result<Parsed<Vec<CpuInfo>>> {
+    let path = proc.join("cpuinfo");
+    let mut parser = Parser::new(&path, mode);
+    let mut cores = convert(std::fs::read_to_string(&path)?.as_str(), &mut parser)?;
+    for core in cores.iter_mut().filter(|core| core.cpu_mhz.is_none()) {
+        core.cpu_mhz = core
+            .processor
+            .and_then(|processor| sysfs_mhz(sys, processor));
+    }
+    Ok(parser.finish(cores))
 }
 
 #[test]
-fn read_cpu_info_test() {
-    let cpu_info_file_path = "/proc/cpuinfo";
-    let result = read_cpu_info(cpu_info_file_path).unwrap();
-    assert!(!result.is_empty());
+fn cpu_info_test() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let sys = SysRoot::new(fixtures.join("sys"));
+    let read = |arch: &str| {
+        let proc = ProcRoot::new(fixtures.join(arch));
+        cpu_info(&proc, &sys, ParseMode::Strict).unwrap().data
+    };
+    let cores = read("proc");
+    assert_eq!(cores.len(), 2);
+    let core = &cores[1];
+    assert_eq!(core.processor, Some(1));
+    assert_eq!(
+        core.model_name.as_deref(),
+        Some("Intel(R) Core(TM) i7-8700 CPU @ 3.20GHz")
+    );
+    assert_eq!(core.microcode, Some(0xf0));
+    assert_eq!(
+        core.cache_size.as_ref().map(|c| c.bytes()),
+        Some(12_288 * 1024)
+    );
+    assert_eq!((core.core_id, core.cpu_cores), (Some(1), Some(6)));
+    assert_eq!(core.fpu, Some(true));
+    assert!(core.flags.as_ref().unwrap().iter().any(|f| f == "avx2"));
+    // frequencies from cpufreq, where there is one, and the board
+    let cores = read("cpuinfo/aarch64");
+    assert_eq!(cores.len(), 4);
+    assert_eq!(
+        (cores[0].cpu_implementer, cores[0].cpu_part),
+        (Some(0x41), Some(0xd08))
+    );
+    assert_eq!(cores[0].cpu_architecture, Some(8));
+    assert_eq!(cores[1].cpu_mhz, Some(600.0));
+    assert!(cores[3].cpu_mhz.is_none());
+    assert_eq!(cores[3].hardware.as_deref(), Some("BCM2835"));
+    assert_eq!(
+        cores[3].board_model.as_deref(),
+        Some("Raspberry Pi 4 Model B Rev 1.4")
+    );
+    assert!(cores[0]
+        .features
+        .as_ref()
+        .unwrap()
+        .iter()
+        .any(|f| f == "asimd"));
+    let cores = read("cpuinfo/riscv64");
+    assert_eq!((cores[2].processor, cores[2].hart), (Some(2), Some(3)));
+    assert_eq!(cores[0].marchid, Some(0x8000000000000007));
+    assert_eq!(cores[0].uarch.as_deref(), Some("sifive,u74-mc"));
+    assert_eq!(cores[0].cpu_mhz, Some(1500.0));
+    let cores = read("cpuinfo/ppc64le");
+    assert_eq!(cores.len(), 2);
+    assert_eq!(cores[1].cpu_mhz, Some(2166.0));
+    assert_eq!(cores[1].platform.as_deref(), Some("PowerNV"));
+    assert_eq!(cores[1].board_model.as_deref(), Some("8335-GTH"));
+    assert_eq!(cores[1].mmu.as_deref(), Some("Radix"));
+    // a bad value skips only itself, an unknown field is kept as written
+    let source =
+        "processor\t: 0\ncpu MHz\t\t: fast\nTLB size\t: 2560 4K pages\nbogomips\t: 6399.96\n";
+    let mut parser = Parser::new(std::path::Path::new("/proc/cpuinfo"), ParseMode::Lenient);
+    let cores = convert(source, &mut parser).unwrap();
+    assert_eq!(cores.len(), 1);
+    assert_eq!(cores[0].bogomips, Some(6399.96));
+    assert!(cores[0].cpu_mhz.is_none());
+    assert_eq!(
+        cores[0].extra.get("TLB size").map(|s| s.as_str()),
+        Some("2560 4K pages")
+    );
+    let warnings = parser.finish(()).warnings;
+    assert_eq!(
+        warnings
+            .iter()
+            .map(|w| (w.line, w.field.as_str()))
+            .collect::<Vec<_>>(),
+        [(2, "cpu MHz")]
+    );
+    let mut parser = Parser::new(std::path::Path::new("/proc/cpuinfo"), ParseMode::Strict);
+    assert_eq!(convert(source, &mut parser).unwrap_err().line, 2);
 }
@@ -2,24 +2,35 @@ use std::{str::Split, u64};
 
 use serde::{Deserialize, Serialize};
 
+use crate::tools::{
+    parse::{number, ParseMode, Parsed, Parser, ProcParseError},
+    ProcRoot,
+};
+
 /// The amount of time, measured in units of USER_HZ
 /// (1/100ths of a second on most architectures, use
 /// sysconf(_SC_CLK_TCK) to obtain the right value)
 pub type UnixTime = u64;
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct CoreStat {
+    /*
+       The N of a cpuN line, None for the cpu line of all of
+       them.  Offline CPUs have no line, so N is not the
+       position in the file.
+    */
+    pub(crate) cpu: Option<u32>,
     // (1) Time spent in user mode.
-    user: Option<UnixTime>,
+    pub(crate) user: Option<UnixTime>,
     // (2) Time spent in user mode with low priority (nice)
-    nice: Option<UnixTime>,
+    pub(crate) nice: Option<UnixTime>,
     // (3) Time spent in system mode.
-    system: Option<UnixTime>,
+    pub(crate) system: Option<UnixTime>,
     /**
         (4) Time spent in the idle task.  This value
         should be USER_HZ times the second entry in
         the /proc/uptime pseudo-file.
     */
-    idle_task: Option<UnixTime>,
+    pub(crate) idle_task: Option<UnixTime>,
     /**
         **(since Linux 2.5.41)**
         (5) Time waiting for I/O to complete.  This
@@ -38,24 +49,24 @@ pub struct CoreStat {
             3. The value in this field may decrease in
         certain conditions.
     */
-    io_wait_unreliable: Option<UnixTime>,
+    pub(crate) io_wait_unreliable: Option<UnixTime>,
     /**
         (since Linux 2.6.0)
         (6) Time servicing interrupts.
     */
-    interrupt: Option<UnixTime>,
+    pub(crate) interrupt: Option<UnixTime>,
     /**
         **(since Linux 2.6.0)**
         (7) Time servicing softirqs.
     */
-    soft_interrupt: Option<UnixTime>,
+    pub(crate) soft_interrupt: Option<UnixTime>,
     /**
         **(since Linux 2.6.11)**
         (8) Stolen time, which is the time spent in
         other operating systems when running in a
         virtualized environment
     */
-    virtual_environment_stolen_time: Option<UnixTime>,
+    pub(crate) virtual_environment_stolen_time: Option<UnixTime>,
     /**
         **(since Linux 2.6.24)**
         (9) Time spent running a virtual CPU for
@@ -76,8 +87,8 @@ type DiskIo = ((u64, u64), (u64, u64, u64, u64, u64));
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct CpuStat {
-    system_cpu: CoreStat,
-    specific_cpu: Vec<CoreStat>,
+    pub(crate) system_cpu: CoreStat,
+    pub(crate) specific_cpu: Vec<CoreStat>,
     /*
        The number of pages the system paged in and the
        number that were paged out (from disk).
@@ -103,27 +114,27 @@ pub struct CpuStat {
        interrupt.  Unnumbered interrupts are not shown,
        only summed into the total.
     */
-    interrupt: Option<Vec<u64>>,
+    pub(crate) interrupt: Option<Vec<u64>>,
     // The number of context switches that the system
     //    underwent.
-    context_switch: Option<u64>,
+    pub(crate) context_switch: Option<u64>,
     /**
        boot time, in seconds since the Epoch, 1970-01-01
        00:00:00 +0000 (UTC).
     */
-    boot_time: Option<u64>,
+    pub(crate) boot_time: Option<u64>,
     // Number of forks since boot.
-    process: Option<u64>,
+    pub(crate) process: Option<u64>,
     /***
         Number of processes in runnable state.
         (Linux 2.5.45 onward.)
     */
-    process_running: Option<u64>,
+    pub(crate) process_running: Option<u64>,
     /**
        Number of processes blocked waiting for I/O to
        complete.  (Linux 2.5.45 onward.)
     */
-    process_blocked: Option<u64>,
+    pub(crate) process_blocked: Option<u64>,
     /**
        This line shows the number of softirq for all CPUs.
        The first column is the total of all softirqs and
@@ -133,136 +144,125 @@ pub struct CpuStat {
     software_interrupt_request: Option<Vec<u64>>,
 }
 
-#[inline]
-fn take_first(source: &str) -> Option<(u64, u64)> {
-    let mut major_disk_idk = source.split(",").map(|s| s.parse::<u64>().unwrap());
-    let major = major_disk_idk.next()?;
-    let disk_idk = major_disk_idk.next()?;
-    Some((major, disk_idk))
+// the numbers of "31,30,5764", fewer than `N` is an error
+fn take_numbers<const N: usize>(source: &str) -> Result<[u64; N], String> {
+    let mut numbers = [0; N];
+    let mut columns = source.split(',');
+    for slot in numbers.iter_mut() {
+        *slot = number(columns.next().ok_or("too few columns")?)?;
+    }
+    Ok(numbers)
 }
 
-#[inline]
-fn take_last(source: &str) -> Option<(u64, u64, u64, u64, u64)> {
-    let mut fives = source.split(",").map(|s| s.parse::<u64>().unwrap());
-    let no_info = fives.next()?;
-    let read_io_ops = fives.next()?;
-    let blks_read = fives.next()?;
-    let write_io_ops = fives.next()?;
-    let blks_written = fives.next()?;
-    Some((no_info, read_io_ops, blks_read, write_io_ops, blks_written))
+// (major,disk_idx):(noinfo,read_io_ops,blks_read,write_io_ops,blks_written)
+fn take_disk_io(source: &str) -> Result<DiskIo, String> {
+    let (first, last) = source
+        .split_once(':')
+        .ok_or_else(|| std::format!("{:?}: no ':'", source))?;
+    let parenthesized = |s: &str| s.trim_matches(|c| c == '(' || c == ')').to_string();
+    let [major, disk_idx] = take_numbers::<2>(parenthesized(first).as_str())?;
+    let [no_info, read_io_ops, blks_read, write_io_ops, blks_written] =
+        take_numbers::<5>(parenthesized(last).as_str())?;
+    Ok((
+        (major, disk_idx),
+        (no_info, read_io_ops, blks_read, write_io_ops, blks_written),
+    ))
 }
+
+// by position, a bad column is left None without shifting the ones after
+// it, and the first one is reported
+fn fill_field(stat: &mut CoreStat, s: Split<char>) -> Result<(), String> {
+    let columns = s
+        .filter(|s| !s.is_empty())
+        .map(number::<u64>)
+        .collect::<Vec<Result<u64, String>>>();
+    let column = |i: usize| columns.get(i).and_then(|c| c.as_ref().ok()).copied();
+    stat.user = column(0);
+    stat.nice = column(1);
+    stat.system = column(2);
+    stat.idle_task = column(3);
+    stat.io_wait_unreliable = column(4);
+    stat.interrupt = column(5);
+    stat.soft_interrupt = column(6);
+    stat.virtual_environment_stolen_time = column(7);
+    stat.virtual_cpu = column(8);
+    stat.virtual_cpu_nice = column(9);
+    match columns
+        .into_iter()
+        .enumerate()
+        .find_map(|(i, c)| c.err().map(|reason| (i, reason)))
+    {
+        Some((i, reason)) => Err(std::format!("column {}: {}", i + 1, reason)),
+        None => Ok(()),
+    }
+}
+
+// the first value after the name
 #[inline]
-fn take_disk_io(source: &str) -> Option<DiskIo> {
-    let mut first_last = source
-        .split(":")
-        .map(|s| s.trim_matches(|c| c == '(' || c == ')'));
-    let first = take_first(first_last.next()?)?;
-    let second = take_last(first_last.next()?)?;
-    Some((first, second))
+fn single(tail: &mut Split<char>) -> Result<u64, String> {
+    number(tail.find(|s| !s.is_empty()).unwrap_or_default())
 }
 
-fn fill_field(mut stat: &mut CoreStat, s: Split<&str>) {
-    let mut i = s
-        .map(|s| s.trim().parse::<u64>())
-        .filter(|s| s.is_ok())
-        .map(|n| n.unwrap());
-    stat.user = i.next();
-    stat.nice = i.next();
-    stat.system = i.next();
-    stat.idle_task = i.next();
-    stat.io_wait_unreliable = i.next();
-    stat.interrupt = i.next();
-    stat.soft_interrupt = i.next();
-    stat.virtual_environment_stolen_time = i.next();
-    stat.virtual_cpu = i.next();
-    stat.virtual_cpu_nice = i.next();
+#[inline]
+fn pair(tail: &mut Split<char>) -> Result<(u64, u64), String> {
+    Ok((single(tail)?, single(tail)?))
 }
 
-fn convert(source: String) -> std::io::Result<CpuStat> {
+pub(crate) fn convert(source: &str, parser: &mut Parser) -> Result<CpuStat, ProcParseError> {
     let mut result = CpuStat::default();
-    let name_splits = source.split("\n").filter(|s| !s.is_empty()).map(
-        |s| -> (Option<&str>, std::str::Split<&str>) {
-            let mut splits = s.split(" ");
-            (splits.next(), splits)
-        },
-    );
-    for (name_or_none, mut tail) in name_splits {
-        if let Some(name) = name_or_none {
-            if name.starts_with("cpu") {
-                if name == "cpu" {
-                    fill_field(&mut result.system_cpu, tail);
-                } else {
-                    let mut x = CoreStat::default();
-                    fill_field(&mut x, tail);
-                    result.specific_cpu.push(x);
-                }
-            } else {
-                match name {
-                    "page" => {
-                        result.page = Some((
-                            tail.next().unwrap().parse::<u64>().unwrap(),
-                            tail.next().unwrap().parse::<u64>().unwrap(),
-                        ))
-                    }
-                    "swap" => {
-                        result.swap = Some((
-                            tail.next().unwrap().parse::<u64>().unwrap(),
-                            tail.next().unwrap().parse::<u64>().unwrap(),
-                        ))
-                    }
-                    // "intr" => {
-                    //     result.interrupt = Some(
-                    //         tail.map(move |s| s.parse::<u64>().unwrap())
-                    //             .collect::<Vec<u64>>(),
-                    //     )
-                    // }
-                    "disk_io" => {
-                        result.disk_io = {
-                            Some({
-                                tail.map(take_disk_io)
-                                    .filter(|s| s.is_some())
-                                    .map(|s| s.unwrap())
-                                    .collect::<Vec<DiskIo>>()
-                            })
-                        }
-                    }
-                    "ctxt" => {
-                        result.context_switch = Some(tail.next().unwrap().parse::<u64>().unwrap())
-                    }
-                    "btime" => {
-                        result.boot_time = Some(tail.next().unwrap().parse::<u64>().unwrap())
-                    }
-                    "processes" => {
-                        result.process = Some(tail.next().unwrap().parse::<u64>().unwrap())
-                    }
-                    "procs_running" => {
-                        result.process_running = Some(tail.next().unwrap().parse::<u64>().unwrap())
-                    }
-                    "procs_blocked" => {
-                        result.process_blocked = Some(tail.next().unwrap().parse::<u64>().unwrap())
-                    }
-                    "softirq" => {
-                        result.software_interrupt_request = Some(
-                            tail.map(|s| s.trim().parse::<u64>())
-                                .filter(|x| x.is_ok())
-                                .map(|x| x.unwrap())
-                                .collect::<Vec<u64>>(),
-                        )
-                    }
-                    _ => {
-                        // println!("unknown {}", name);
-                    }
-                }
+    for (index, line) in source.split('\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let mut tail = line.split(' ');
+        let name = tail.next().unwrap_or_default();
+        let parsed = match name {
+            "cpu" => fill_field(&mut result.system_cpu, tail),
+            _ if name.starts_with("cpu") => {
+                let mut x = CoreStat::default();
+                let filled = number::<u32>(&name[3..])
+                    .map(|cpu| x.cpu = Some(cpu))
+                    .and_then(|_| fill_field(&mut x, tail));
+                result.specific_cpu.push(x);
+                filled
             }
+            "page" => pair(&mut tail).map(|v| result.page = Some(v)),
+            "swap" => pair(&mut tail).map(|v| result.swap = Some(v)),
+            "intr" => tail
+                .filter(|s| !s.is_empty())
+                .map(number::<u64>)
+                .collect::<Result<Vec<u64>, String>>()
+                .map(|v| result.interrupt = Some(v)),
+            // "disk_io:" as Linux 2.4 writes it
+            "disk_io" | "disk_io:" => tail
+                .filter(|s| !s.is_empty())
+                .map(take_disk_io)
+                .collect::<Result<Vec<DiskIo>, String>>()
+                .map(|v| result.disk_io = Some(v)),
+            "ctxt" => single(&mut tail).map(|v| result.context_switch = Some(v)),
+            "btime" => single(&mut tail).map(|v| result.boot_time = Some(v)),
+            "processes" => single(&mut tail).map(|v| result.process = Some(v)),
+            "procs_running" => single(&mut tail).map(|v| result.process_running = Some(v)),
+            "procs_blocked" => single(&mut tail).map(|v| result.process_blocked = Some(v)),
+            "softirq" => tail
+                .filter(|s| !s.is_empty())
+                .map(number::<u64>)
+                .collect::<Result<Vec<u64>, String>>()
+                .map(|v| result.software_interrupt_request = Some(v)),
+            _ => Err("unknown field".to_string()),
+        };
+        if let Err(reason) = parsed {
+            parser.problem(index + 1, name, reason)?;
         }
     }
     Ok(result)
 }
 
-pub fn cpu_stat() -> std::io::Result<CpuStat> {
-    let cpu_stat_file_content = std::fs::read_to_string("/proc/stat")?;
-    let result = convert(cpu_stat_file_content)?;
-    Ok(result)
+pub fn cpu_stat(proc: &ProcRoot, mode: ParseMode) -> std::io::Result<Parsed<CpuStat>> {
+    let path = proc.join("stat");
+    let mut parser = Parser::new(&path, mode);
+    let stat = convert(std::fs::read_to_string(&path)?.as_str(), &mut parser)?;
+    Ok(parser.finish(stat))
 }
 
 #[test]
@@ -287,5 +287,40 @@ processes 45099
 procs_running 4
 procs_blocked 0
 softirq 159081905 47839458 11367052 19 849814 8102 0 749747 59052904 17481 39197328"#;
-    assert!(convert(source.to_string()).is_ok());
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    assert!(convert(source, &mut parser).is_ok());
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    let error = convert("cpu  1 2 3\nctxt lots\n", &mut parser).unwrap_err();
+    assert_eq!((error.line, error.field.as_str()), (2, "ctxt"));
+    // the columns after a bad one stay where they are
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Lenient);
+    let stat = convert("cpu  1 2 x 4 5\ncpu0 1 2 3 4 5\n", &mut parser).unwrap();
+    assert_eq!(
+        (stat.system_cpu.system, stat.system_cpu.idle_task),
+        (None, Some(4))
+    );
+    assert_eq!(stat.specific_cpu[0].io_wait_unreliable, Some(5));
+    let warnings = parser.finish(()).warnings;
+    assert_eq!((warnings.len(), warnings[0].field.as_str()), (1, "cpu"));
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    assert!(convert("cpu3 1 2 x 4 5\n", &mut parser).is_err());
+    let disk_io = "disk_io: (3,0):(31,30,5764,1,2) (3,1):(1,x,2,3,4)\n";
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    let error = convert(disk_io, &mut parser).unwrap_err();
+    assert_eq!(error.field, "disk_io:");
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    let stat = convert("disk_io: (3,0):(31,30,5764,1,2)\n", &mut parser).unwrap();
+    assert_eq!(stat.disk_io, Some(vec![((3, 0), (31, 30, 5764, 1, 2))]));
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let stat = cpu_stat(&ProcRoot::new(fixtures.join("proc")), ParseMode::Strict)
+        .unwrap()
+        .data;
+    assert_eq!(stat.specific_cpu.len(), 2);
+    assert_eq!(stat.system_cpu.user, Some(5084991));
+    assert_eq!(stat.context_switch, Some(562378222));
+    let interrupt = stat.interrupt.unwrap();
+    assert_eq!(interrupt[0], 197172306);
+    assert_eq!(interrupt[2], 473);
+    assert_eq!(stat.boot_time, Some(1623002973));
+    assert_eq!(stat.process_running, Some(4));
 }
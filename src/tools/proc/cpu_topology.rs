@@ -0,0 +1,242 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    sysfs::{numbered, read_value},
+    BitType, SysRoot,
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Core {
+    // topology/core_id, unique within its socket only
+    id: u32,
+    // logical processors, the N of cpuN
+    threads: Vec<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Socket {
+    // topology/physical_package_id
+    id: u32,
+    cores: Vec<Core>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Cache {
+    // 1 for L1, ...
+    level: Option<u32>,
+    // Data, Instruction or Unified
+    kind: Option<String>,
+    size: Option<BitType>,
+    line_size: Option<u32>,
+    ways_of_associativity: Option<u32>,
+    // the logical processors sharing this cache
+    shared_cpus: Vec<u32>,
+}
+
+/// How the logical processors are laid out.  Read once, it only changes
+/// when processors are taken offline.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CpuTopology {
+    sockets: Vec<Socket>,
+    // every cache once, from L1 up
+    caches: Vec<Cache>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct NumaNode {
+    id: u32,
+    cpus: Vec<u32>,
+    // from nodeN/meminfo
+    mem_total: Option<BitType>,
+    mem_free: Option<BitType>,
+    mem_used: Option<BitType>,
+}
+
+// e.g. "0-3,8-11"
+fn cpu_list(source: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+    for range in source.trim().split(',').filter(|s| !s.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                if let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) {
+                    cpus.extend(first..=last);
+                }
+            }
+            None => cpus.extend(range.parse::<u32>().ok()),
+        }
+    }
+    cpus
+}
+
+// e.g. "32K" or "8M", sysfs means KiB and MiB
+fn cache_size(source: &str) -> Option<BitType> {
+    let source = source.trim();
+    let (number, shift) = match source.chars().last()? {
+        'K' => (&source[..source.len() - 1], 10),
+        'M' => (&source[..source.len() - 1], 20),
+        'G' => (&source[..source.len() - 1], 30),
+        _ => (source, 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(1 << shift)
+        .map(BitType::from_bytes)
+}
+
+fn read_caches(cpu: &Path) -> Vec<Cache> {
+    numbered(&cpu.join("cache"), "index", "")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|index| {
+            let path = cpu.join(std::format!("cache/index{}", index));
+            Cache {
+                level: read_value(&path.join("level")),
+                kind: read_value(&path.join("type")),
+                size: std::fs::read_to_string(path.join("size"))
+                    .ok()
+                    .and_then(|s| cache_size(s.as_str())),
+                line_size: read_value(&path.join("coherency_line_size")),
+                ways_of_associativity: read_value(&path.join("ways_of_associativity")),
+                shared_cpus: std::fs::read_to_string(path.join("shared_cpu_list"))
+                    .map(|s| cpu_list(s.as_str()))
+                    .unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// Sockets, cores and threads of the online processors, and their caches.
+pub fn cpu_topology(sys: &SysRoot) -> std::io::Result<CpuTopology> {
+    let cpu_path = sys.join("devices/system/cpu");
+    let mut sockets = BTreeMap::<u32, BTreeMap<u32, Vec<u32>>>::new();
+    let mut caches = Vec::<Cache>::new();
+    for cpu in numbered(&cpu_path, "cpu", "")? {
+        let path = cpu_path.join(std::format!("cpu{}", cpu));
+        // offline processors have no topology
+        if !path.join("topology").is_dir() {
+            continue;
+        }
+        // -1 when the firmware does not tell, on some arm boards and older
+        // kernels, there is one socket then
+        let package = read_value::<i32>(&path.join("topology/physical_package_id"))
+            .map_or(0, |id| id.max(0) as u32);
+        // a processor without a core id is a core of its own
+        let core = read_value::<i32>(&path.join("topology/core_id"))
+            .filter(|id| *id >= 0)
+            .map_or(cpu, |id| id as u32);
+        sockets
+            .entry(package)
+            .or_default()
+            .entry(core)
+            .or_default()
+            .push(cpu);
+        // a cache shared by several processors is listed under each
+        for cache in read_caches(&path) {
+            if !caches.iter().any(|c| {
+                (c.level, &c.kind, &c.shared_cpus) == (cache.level, &cache.kind, &cache.shared_cpus)
+            }) {
+                caches.push(cache);
+            }
+        }
+    }
+    caches.sort_by_key(|c| (c.level, c.shared_cpus.first().copied()));
+    Ok(CpuTopology {
+        sockets: sockets
+            .into_iter()
+            .map(|(id, cores)| Socket {
+                id,
+                cores: cores
+                    .into_iter()
+                    .map(|(id, threads)| Core { id, threads })
+                    .collect(),
+            })
+            .collect(),
+        caches,
+    })
+}
+
+// "Node 0 MemTotal:       16265236 kB"
+fn node_memory(node: &mut NumaNode, source: &str) {
+    for line in source.split('\n') {
+        let (name, value) = match line.split_once(':') {
+            // after "Node 0"
+            Some((name, value)) => (
+                name.rsplit(' ').next().unwrap_or_default(),
+                BitType::from_kernel(value).ok(),
+            ),
+            None => continue,
+        };
+        match name {
+            "MemTotal" => node.mem_total = value,
+            "MemFree" => node.mem_free = value,
+            "MemUsed" => node.mem_used = value,
+            _ => {}
+        }
+    }
+}
+
+/// Memory of each NUMA node, a single node on most machines.  Kernels
+/// without NUMA support have no nodes at all.
+pub fn numa_nodes(sys: &SysRoot) -> std::io::Result<Vec<NumaNode>> {
+    let node_path = sys.join("devices/system/node");
+    let ids = match numbered(&node_path, "node", "") {
+        Ok(ids) => ids,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(ids
+        .into_iter()
+        .map(|id| {
+            let path = node_path.join(std::format!("node{}", id));
+            let mut node = NumaNode {
+                id,
+                cpus: std::fs::read_to_string(path.join("cpulist"))
+                    .map(|s| cpu_list(s.as_str()))
+                    .unwrap_or_default(),
+                ..NumaNode::default()
+            };
+            if let Ok(meminfo) = std::fs::read_to_string(path.join("meminfo")) {
+                node_memory(&mut node, meminfo.as_str());
+            }
+            node
+        })
+        .collect())
+}
+
+#[test]
+fn cpu_topology_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let sys = SysRoot::new(fixtures.join("sys"));
+    let topology = cpu_topology(&sys).unwrap();
+    // cpu4 is offline, cpu3 has a physical_package_id of -1
+    assert_eq!(topology.sockets.len(), 1);
+    let cores = &topology.sockets[0].cores;
+    assert_eq!(
+        cores
+            .iter()
+            .map(|c| (c.id, c.threads.clone()))
+            .collect::<Vec<_>>(),
+        [(0, vec![0, 2]), (1, vec![1, 3])]
+    );
+    // L1d, L1i and L2 of each core, one L3
+    assert_eq!(topology.caches.len(), 7);
+    let l3 = topology.caches.last().unwrap();
+    assert_eq!(
+        (l3.level, l3.size.map(|b| b.bytes())),
+        (Some(3), Some(12_288 << 10))
+    );
+    assert_eq!(l3.shared_cpus, [0, 1, 2, 3]);
+    assert_eq!(topology.caches[0].kind.as_deref(), Some("Data"));
+    let nodes = numa_nodes(&sys).unwrap();
+    assert_eq!(nodes[0].cpus, [0, 1, 2, 3]);
+    assert_eq!(
+        nodes[0].mem_total.map(|b| b.bytes()),
+        Some(16_265_236 << 10)
+    );
+    assert_eq!(nodes[0].mem_used.map(|b| b.bytes()), Some(7_922_624 << 10));
+    assert_eq!(cpu_list("0-2,5,7-8\n"), [0, 1, 2, 5, 7, 8]);
+    assert_eq!(cache_size("8M").map(|b| b.bytes()), Some(8 << 20));
+}
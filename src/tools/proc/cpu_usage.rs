@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::cpu_stat::{CoreStat, CpuStat, UnixTime};
+
+/// Share of the elapsed jiffies, in percent, spent in each state
+/// between two `CoreStat` readings.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CoreUsage {
+    // the N of cpuN, None for the total
+    pub cpu: Option<u32>,
+    pub user: f64,
+    pub nice: f64,
+    pub system: f64,
+    pub idle: f64,
+    pub io_wait: f64,
+    pub irq: f64,
+    pub soft_irq: f64,
+    pub steal: f64,
+    // everything but idle and io_wait
+    pub busy: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct CpuUsage {
+    // seconds between the two readings, jiffies over USER_HZ
+    pub interval: f64,
+    pub total: CoreUsage,
+    pub cores: Vec<CoreUsage>,
+}
+
+/// USER_HZ, the unit of every time in /proc/stat
+pub fn clock_ticks() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        n if n > 0 => n as u64,
+        _ => 100,
+    }
+}
+
+// guest time is already accounted in user and nice
+#[inline]
+fn jiffies(stat: &CoreStat) -> [UnixTime; 8] {
+    [
+        stat.user.unwrap_or(0),
+        stat.nice.unwrap_or(0),
+        stat.system.unwrap_or(0),
+        stat.idle_task.unwrap_or(0),
+        stat.io_wait_unreliable.unwrap_or(0),
+        stat.interrupt.unwrap_or(0),
+        stat.soft_interrupt.unwrap_or(0),
+        stat.virtual_environment_stolen_time.unwrap_or(0),
+    ]
+}
+
+// iowait may go backwards, so counters are compared with saturating_sub
+fn core_usage(previous: &CoreStat, current: &CoreStat) -> (CoreUsage, UnixTime) {
+    let cpu = current.cpu;
+    let (previous, current) = (jiffies(previous), jiffies(current));
+    let mut delta = [0 as UnixTime; 8];
+    for (d, (c, p)) in delta.iter_mut().zip(current.iter().zip(previous.iter())) {
+        *d = c.saturating_sub(*p);
+    }
+    let total = delta.iter().sum::<UnixTime>();
+    let percent = |d: UnixTime| {
+        if total == 0 {
+            0.0
+        } else {
+            d as f64 * 100.0 / total as f64
+        }
+    };
+    let usage = CoreUsage {
+        cpu,
+        user: percent(delta[0]),
+        nice: percent(delta[1]),
+        system: percent(delta[2]),
+        idle: percent(delta[3]),
+        io_wait: percent(delta[4]),
+        irq: percent(delta[5]),
+        soft_irq: percent(delta[6]),
+        steal: percent(delta[7]),
+        busy: if total == 0 {
+            0.0
+        } else {
+            100.0 - percent(delta[3] + delta[4])
+        },
+    };
+    (usage, total)
+}
+
+/// Utilisation between two `cpu_stat()` readings, `previous` taken first.
+/// Cores are matched by their cpuN id, one that went offline or came
+/// online in between has no usage.
+pub fn cpu_usage(previous: &CpuStat, current: &CpuStat) -> CpuUsage {
+    let (total, jiffies) = core_usage(&previous.system_cpu, &current.system_cpu);
+    let before = previous
+        .specific_cpu
+        .iter()
+        .filter_map(|p| Some((p.cpu?, p)))
+        .collect::<HashMap<u32, &CoreStat>>();
+    let cores = current
+        .specific_cpu
+        .iter()
+        .filter_map(|c| Some(core_usage(before.get(&c.cpu?)?, c).0))
+        .collect::<Vec<CoreUsage>>();
+    // the aggregate line counts jiffies of every core
+    let interval = jiffies as f64 / cores.len().max(1) as f64 / clock_ticks() as f64;
+    CpuUsage {
+        interval,
+        total,
+        cores,
+    }
+}
+
+#[test]
+fn cpu_usage_test() {
+    use crate::tools::{
+        cpu_stat::convert,
+        parse::{ParseMode, Parser},
+    };
+    let mut parser = Parser::new(std::path::Path::new("/proc/stat"), ParseMode::Strict);
+    let previous = convert(
+        "cpu  1000 0 500 8000 500 0 0 0 0 0\ncpu0 500 0 250 4000 250 0 0 0 0 0\ncpu1 500 0 250 4000 250 0 0 0 0 0\n",
+        &mut parser,
+    )
+    .unwrap();
+    let current = convert(
+        "cpu  1100 0 500 8100 400 0 0 0 0 0\ncpu0 600 0 250 4000 250 0 0 0 0 0\ncpu1 500 0 250 4100 250 0 0 0 0 0\n",
+        &mut parser,
+    )
+    .unwrap();
+    let usage = cpu_usage(&previous, &current);
+    // iowait went backwards, it counts as no time at all
+    assert_eq!(usage.total.user, 50.0);
+    assert_eq!(usage.total.io_wait, 0.0);
+    assert_eq!(usage.total.busy, 50.0);
+    assert_eq!(usage.cores.len(), 2);
+    assert_eq!(usage.cores[0].busy, 100.0);
+    assert_eq!(usage.cores[1].idle, 100.0);
+    // cpu1 went offline, cpu2 is not compared with it
+    let current = convert(
+        "cpu  1100 0 500 8100 400 0 0 0 0 0\ncpu0 600 0 250 4000 250 0 0 0 0 0\ncpu2 500 0 250 4100 250 0 0 0 0 0\n",
+        &mut parser,
+    )
+    .unwrap();
+    let usage = cpu_usage(&previous, &current);
+    assert_eq!(usage.cores.len(), 1);
+    assert_eq!(usage.cores[0].cpu, Some(0));
+    assert!(usage.total.cpu.is_none());
+}
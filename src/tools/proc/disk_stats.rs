@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ProcRoot;
+
+// /proc/diskstats counts sectors of 512 bytes, whatever the device uses
+const SECTOR_SIZE: u64 = 512;
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DiskStat {
+    // (1) major number
+    major: u32,
+    // (2) minor number
+    minor: u32,
+    // (3) device name
+    name: String,
+    // (4) This is the total number of reads completed successfully.
+    reads: Option<u64>,
+    /*
+       (5) Reads and writes which are adjacent to each other
+       may be merged for efficiency.
+    */
+    reads_merged: Option<u64>,
+    // (6) This is the total number of sectors read successfully.
+    sectors_read: Option<u64>,
+    // (7) This is the total number of milliseconds spent by all reads.
+    read_time: Option<u64>,
+    // (8) This is the total number of writes completed successfully.
+    writes: Option<u64>,
+    // (9) See the description of field 5.
+    writes_merged: Option<u64>,
+    // (10) This is the total number of sectors written successfully.
+    sectors_written: Option<u64>,
+    // (11) This is the total number of milliseconds spent by all writes.
+    write_time: Option<u64>,
+    /*
+       (12) The only field that should go to zero.
+       Incremented as requests are given to appropriate
+       struct request_queue and decremented as they finish.
+    */
+    in_progress: Option<u64>,
+    /*
+       (13) This field increases so long as field 12 is
+       nonzero, milliseconds spent doing I/Os.
+    */
+    io_time: Option<u64>,
+    /*
+       (14) Weighted number of milliseconds spent doing I/Os,
+       the time in queue.
+    */
+    weighted_io_time: Option<u64>,
+    // (15) discards completed successfully (since Linux 4.18)
+    discards: Option<u64>,
+    // (16) discards merged (since Linux 4.18)
+    discards_merged: Option<u64>,
+    // (17) sectors discarded (since Linux 4.18)
+    sectors_discarded: Option<u64>,
+    // (18) milliseconds spent discarding (since Linux 4.18)
+    discard_time: Option<u64>,
+    // (19) flush requests completed successfully (since Linux 5.5)
+    flushes: Option<u64>,
+    // (20) milliseconds spent flushing (since Linux 5.5)
+    flush_time: Option<u64>,
+}
+
+/// Per second rates of one device between two readings.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct DiskRate {
+    pub name: String,
+    pub read_iops: f64,
+    pub write_iops: f64,
+    pub read_bytes: f64,
+    pub write_bytes: f64,
+    // percent of the time the device had I/O in flight
+    pub utilisation: f64,
+    // average milliseconds a completed request spent, queue included
+    pub await_time: f64,
+}
+
+fn convert_line(line: &str) -> Option<DiskStat> {
+    let mut fields = line.split_whitespace();
+    let major = fields.next()?.parse::<u32>().ok()?;
+    let minor = fields.next()?.parse::<u32>().ok()?;
+    let name = fields.next()?.to_string();
+    // older kernels stop after (14), newer ones after (18) or (20)
+    let values = fields
+        .map(|s| s.parse::<u64>().ok())
+        .collect::<Vec<Option<u64>>>();
+    let value = |i: usize| values.get(i).copied().flatten();
+    Some(DiskStat {
+        major,
+        minor,
+        name,
+        reads: value(0),
+        reads_merged: value(1),
+        sectors_read: value(2),
+        read_time: value(3),
+        writes: value(4),
+        writes_merged: value(5),
+        sectors_written: value(6),
+        write_time: value(7),
+        in_progress: value(8),
+        io_time: value(9),
+        weighted_io_time: value(10),
+        discards: value(11),
+        discards_merged: value(12),
+        sectors_discarded: value(13),
+        discard_time: value(14),
+        flushes: value(15),
+        flush_time: value(16),
+    })
+}
+
+fn convert(source: &str) -> Vec<DiskStat> {
+    source.split('\n').filter_map(convert_line).collect()
+}
+
+pub fn disk_stats(proc: &ProcRoot) -> std::io::Result<Vec<DiskStat>> {
+    Ok(convert(
+        std::fs::read_to_string(proc.join("diskstats"))?.as_str(),
+    ))
+}
+
+#[inline]
+fn delta(previous: Option<u64>, current: Option<u64>) -> u64 {
+    current.unwrap_or(0).saturating_sub(previous.unwrap_or(0))
+}
+
+/// Rates between two `disk_stats()` readings taken `seconds` apart, for the
+/// devices present in both.
+pub fn disk_rates(previous: &[DiskStat], current: &[DiskStat], seconds: f64) -> Vec<DiskRate> {
+    if seconds <= 0.0 {
+        return Vec::new();
+    }
+    current
+        .iter()
+        .filter_map(|c| {
+            let p = previous.iter().find(|p| p.name == c.name)?;
+            let reads = delta(p.reads, c.reads);
+            let writes = delta(p.writes, c.writes);
+            let wait = delta(p.read_time, c.read_time) + delta(p.write_time, c.write_time);
+            Some(DiskRate {
+                name: c.name.clone(),
+                read_iops: reads as f64 / seconds,
+                write_iops: writes as f64 / seconds,
+                read_bytes: (delta(p.sectors_read, c.sectors_read) * SECTOR_SIZE) as f64 / seconds,
+                write_bytes: (delta(p.sectors_written, c.sectors_written) * SECTOR_SIZE) as f64
+                    / seconds,
+                utilisation: (delta(p.io_time, c.io_time) as f64 / (seconds * 10.0)).min(100.0),
+                await_time: if reads + writes == 0 {
+                    0.0
+                } else {
+                    wait as f64 / (reads + writes) as f64
+                },
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn disk_stats_test() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let disks = disk_stats(&ProcRoot::new(fixtures.join("proc"))).unwrap();
+    assert_eq!(disks.len(), 6);
+    let nvme = &disks[1];
+    assert_eq!(
+        (nvme.major, nvme.minor, nvme.name.as_str()),
+        (259, 0, "nvme0n1")
+    );
+    assert_eq!(nvme.sectors_written, Some(27712544));
+    assert_eq!(nvme.sectors_discarded, Some(163282216));
+    assert_eq!(nvme.flush_time, Some(16864));
+    // a 4.x kernel without discard and flush fields
+    let sda = disks.iter().find(|d| d.name == "sda").unwrap();
+    assert_eq!(sda.weighted_io_time, Some(35332));
+    assert!(sda.discards.is_none());
+    let previous = convert("   8       0 sda 100 0 800 50 100 0 1600 150 0 400 200\n");
+    let current = convert("   8       0 sda 300 0 2800 250 200 0 3600 350 1 900 600\n");
+    let rates = disk_rates(&previous, &current, 2.0);
+    assert_eq!(rates[0].read_iops, 100.0);
+    assert_eq!(rates[0].write_iops, 50.0);
+    assert_eq!(rates[0].read_bytes, 512_000.0);
+    assert_eq!(rates[0].utilisation, 25.0);
+    assert_eq!(rates[0].await_time, 400.0 / 300.0);
+}
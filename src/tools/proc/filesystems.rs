@@ -0,0 +1,396 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{BitType, ProcRoot};
+
+// kernel interfaces and virtual filesystems that hold no user data
+static PSEUDO_FILESYSTEMS: [&str; 24] = [
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "proc",
+    "pstore",
+    "ramfs",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "squashfs",
+    "sysfs",
+    "tracefs",
+    "fuse.gvfsd-fuse",
+];
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Filesystem {
+    // (3) major:minor, the value of st_dev for files on this filesystem
+    device: String,
+    // (10) filesystem-specific information or "none"
+    source: String,
+    // (5) mount point relative to the process's root directory
+    mount_point: String,
+    // (9) filesystem type in the form "type[.subtype]"
+    fs_type: String,
+    // (6) per-mount options
+    options: Vec<String>,
+    // (11) per-superblock options
+    super_options: Vec<String>,
+    // mounted read-only, e.g. `mount -o remount,ro`
+    read_only: bool,
+    /*
+       The superblock is read-only while the mount may not
+       be, what ext4 does after an error with
+       errors=remount-ro.
+    */
+    super_read_only: bool,
+    // the errors= superblock option: continue, remount-ro or panic
+    on_error: Option<String>,
+    // statvfs(3), `None` when the call failed
+    size: Option<BitType>,
+    used: Option<BitType>,
+    // free blocks available to unprivileged users
+    available: Option<BitType>,
+    inodes: Option<u64>,
+    inodes_used: Option<u64>,
+    inodes_free: Option<u64>,
+}
+
+/// What statvfs(3) tells about a mounted filesystem, in bytes.
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    size: u64,
+    free: u64,
+    available: u64,
+    inodes: u64,
+    inodes_free: u64,
+}
+
+// the field types are c_ulong or fsblkcnt_t, narrower on 32-bit targets
+#[allow(clippy::unnecessary_cast)]
+fn statvfs(mount_point: &str) -> Option<Usage> {
+    let path = CString::new(mount_point).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    let fragment = stat.f_frsize as u64;
+    Some(Usage {
+        size: stat.f_blocks as u64 * fragment,
+        free: stat.f_bfree as u64 * fragment,
+        available: stat.f_bavail as u64 * fragment,
+        inodes: stat.f_files as u64,
+        inodes_free: stat.f_ffree as u64,
+    })
+}
+
+// statvfs(3) calls at once, a mount that hangs keeps one of them busy
+const WORKERS: usize = 4;
+
+type Answer = (String, Option<Usage>);
+
+// the queue of mount points to call statvfs on, and their answers
+fn spawn_workers() -> Option<(Sender<String>, Receiver<Answer>)> {
+    let (jobs, queue) = mpsc::channel::<String>();
+    let queue = Arc::new(Mutex::new(queue));
+    let (answer, answers) = mpsc::channel();
+    let mut spawned = 0;
+    for _ in 0..WORKERS {
+        let (queue, answer) = (queue.clone(), answer.clone());
+        let worker = std::thread::Builder::new()
+            .name("statvfs".to_string())
+            .spawn(move || loop {
+                let job = queue
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .recv();
+                // ends with the probe that sends the jobs
+                let mount_point = match job {
+                    Ok(mount_point) => mount_point,
+                    Err(_) => break,
+                };
+                let usage = statvfs(mount_point.as_str());
+                if answer.send((mount_point, usage)).is_err() {
+                    break;
+                }
+            });
+        spawned += worker.is_ok() as usize;
+    }
+    if spawned == 0 {
+        return None;
+    }
+    Some((jobs, answers))
+}
+
+// an answer for a mount point that is no longer asked about is dropped
+fn record(known: &mut HashMap<String, Usage>, pending: &mut HashSet<String>, answer: Answer) {
+    let (mount_point, usage) = answer;
+    if !pending.remove(&mount_point) {
+        return;
+    }
+    match usage {
+        Some(usage) => {
+            known.insert(mount_point, usage);
+        }
+        // statvfs failed
+        None => {
+            known.remove(&mount_point);
+        }
+    }
+}
+
+/// Calls statvfs(3) on a few long-lived threads, as it blocks for as
+/// long as a network server does not answer.  A mount that did not answer
+/// in time keeps its last known usage, and is not asked again before its
+/// pending call returned.
+#[derive(Default)]
+pub struct UsageProbe {
+    // started on the first call of `usages`
+    workers: Option<(Sender<String>, Receiver<Answer>)>,
+    known: HashMap<String, Usage>,
+    pending: HashSet<String>,
+}
+
+impl UsageProbe {
+    // waits up to `timeout` for the calls it starts, not for pending ones
+    fn usages(&mut self, mount_points: &[String], timeout: Duration) -> HashMap<String, Usage> {
+        let deadline = Instant::now() + timeout;
+        // unmounted since, a hung call for one of them is just forgotten
+        self.known
+            .retain(|mount_point, _| mount_points.contains(mount_point));
+        self.pending
+            .retain(|mount_point| mount_points.contains(mount_point));
+        if self.workers.is_none() {
+            self.workers = spawn_workers();
+        }
+        let (jobs, answers) = match &self.workers {
+            Some(workers) => workers,
+            None => return self.known.clone(),
+        };
+        // answers that came after the previous deadline
+        while let Ok(answer) = answers.try_recv() {
+            record(&mut self.known, &mut self.pending, answer);
+        }
+        let mut started = Vec::new();
+        for mount_point in mount_points.iter() {
+            if !self.pending.contains(mount_point) && jobs.send(mount_point.clone()).is_ok() {
+                self.pending.insert(mount_point.clone());
+                started.push(mount_point);
+            }
+        }
+        while started
+            .iter()
+            .any(|mount_point| self.pending.contains(*mount_point))
+        {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match answers.recv_timeout(wait) {
+                Ok(answer) => record(&mut self.known, &mut self.pending, answer),
+                Err(_) => break,
+            }
+        }
+        self.known.clone()
+    }
+}
+
+// space, tab, newline and backslash are written as \ooo
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                result.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                result.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+#[inline]
+fn options(field: &str) -> Vec<String> {
+    field.split(',').map(|s| s.to_string()).collect()
+}
+
+// 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+fn convert_line(line: &str) -> Option<Filesystem> {
+    let (head, tail) = line.split_once(" - ")?;
+    let head = head.split(' ').collect::<Vec<&str>>();
+    let mut tail = tail.split(' ');
+    let fs_type = tail.next()?.to_string();
+    let source = unescape(tail.next()?);
+    let super_options = options(tail.next().unwrap_or_default());
+    let options = options(head.get(5)?);
+    Some(Filesystem {
+        device: head.get(2)?.to_string(),
+        source,
+        mount_point: unescape(head.get(4)?),
+        fs_type,
+        read_only: options.iter().any(|o| o == "ro"),
+        super_read_only: super_options.iter().any(|o| o == "ro"),
+        on_error: super_options
+            .iter()
+            .find_map(|o| o.strip_prefix("errors="))
+            .map(|s| s.to_string()),
+        options,
+        super_options,
+        ..Filesystem::default()
+    })
+}
+
+// `usages` of the mount points it is given, by mount point
+fn read_filesystems(
+    mountinfo: &Path,
+    usages: impl FnOnce(&[String]) -> HashMap<String, Usage>,
+) -> std::io::Result<Vec<Filesystem>> {
+    let mut filesystems: Vec<Filesystem> = Vec::new();
+    for filesystem in std::fs::read_to_string(mountinfo)?
+        .split('\n')
+        .filter_map(convert_line)
+        .filter(|fs| !PSEUDO_FILESYSTEMS.contains(&fs.fs_type.as_str()))
+    {
+        // a later mount on the same point hides the earlier one
+        filesystems.retain(|fs| fs.mount_point != filesystem.mount_point);
+        filesystems.push(filesystem);
+    }
+    let usages = usages(
+        &filesystems
+            .iter()
+            .map(|fs| fs.mount_point.clone())
+            .collect::<Vec<String>>(),
+    );
+    for filesystem in filesystems.iter_mut() {
+        if let Some(usage) = usages.get(filesystem.mount_point.as_str()) {
+            filesystem.size = Some(BitType::from_bytes(usage.size));
+            filesystem.used = Some(BitType::from_bytes(usage.size.saturating_sub(usage.free)));
+            filesystem.available = Some(BitType::from_bytes(usage.available));
+            filesystem.inodes = Some(usage.inodes);
+            filesystem.inodes_used = Some(usage.inodes.saturating_sub(usage.inodes_free));
+            filesystem.inodes_free = Some(usage.inodes_free);
+        }
+    }
+    // filesystems without blocks (e.g. some fuse mounts) are pseudo as well
+    filesystems.retain(|fs| fs.size != Some(BitType::default()));
+    Ok(filesystems)
+}
+
+/// Mounted filesystems holding data and their usage, the usage is waited
+/// for up to `timeout`, see `UsageProbe`.  With another procfs than the
+/// local one the usage is left out, statvfs(3) would answer for the
+/// mounts of serverd and not for those of the watched machine.
+pub fn filesystems(
+    proc: &ProcRoot,
+    probe: &mut UsageProbe,
+    timeout: Duration,
+) -> std::io::Result<Vec<Filesystem>> {
+    read_filesystems(&proc.namespaced("mountinfo"), |mount_points| {
+        if proc.is_local() {
+            probe.usages(mount_points, timeout)
+        } else {
+            HashMap::new()
+        }
+    })
+}
+
+#[test]
+fn filesystems_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    // a captured tree, its usage is not the one of this machine
+    let captured = filesystems(
+        &ProcRoot::new(fixtures.join("proc")),
+        &mut UsageProbe::default(),
+        Duration::from_secs(5),
+    )
+    .unwrap();
+    assert!(captured.iter().all(|fs| fs.size.is_none()));
+    // read from init, self/ would be the mounts of serverd
+    assert_eq!(captured.len(), 4);
+    assert_eq!(
+        ProcRoot::default().namespaced("mountinfo"),
+        Path::new("/proc/self/mountinfo")
+    );
+    let usage = |mount_points: &[String]| {
+        let mut usages = HashMap::new();
+        usages.insert("/run".to_string(), Usage::default());
+        usages.insert(
+            "/".to_string(),
+            Usage {
+                size: 100 << 30,
+                free: 40 << 30,
+                available: 35 << 30,
+                inodes: 6_553_600,
+                inodes_free: 6_000_000,
+            },
+        );
+        usages.retain(|mount_point, _| mount_points.contains(mount_point));
+        usages
+    };
+    let filesystems = read_filesystems(&fixtures.join("proc/1/mountinfo"), usage).unwrap();
+    assert_eq!(
+        filesystems
+            .iter()
+            .map(|fs| fs.mount_point.as_str())
+            .collect::<Vec<&str>>(),
+        ["/", "/boot/efi", "/mnt/backup disk"]
+    );
+    let root = &filesystems[0];
+    assert_eq!(root.source, "/dev/nvme0n1p2");
+    assert_eq!(root.device, "259:2");
+    assert_eq!(
+        (
+            root.used.map(|b| b.bytes()),
+            root.available.map(|b| b.bytes())
+        ),
+        (Some(60 << 30), Some(35 << 30))
+    );
+    assert_eq!(root.inodes_used, Some(553_600));
+    assert_eq!(root.on_error.as_deref(), Some("remount-ro"));
+    assert!(!root.read_only && !root.super_read_only);
+    // remounted read-only after an error, the mount still says rw
+    let backup = &filesystems[2];
+    assert!(!backup.read_only && backup.super_read_only);
+    assert!(backup.size.is_none());
+    assert!(statvfs("/").unwrap().size > 0);
+
+    let mut probe = UsageProbe::default();
+    let mount_points = ["/".to_string(), "/nonexistent".to_string()];
+    let usages = probe.usages(&mount_points, Duration::from_secs(5));
+    assert!(usages["/"].size > 0);
+    assert!(!usages.contains_key("/nonexistent"));
+    // a call that did not come back keeps the last known usage
+    probe.pending.insert("/".to_string());
+    let usages = probe.usages(&mount_points[..1], Duration::from_secs(5));
+    assert!(usages["/"].size > 0);
+    assert!(probe.pending.contains("/"));
+    // until it is unmounted
+    assert!(probe.usages(&[], Duration::from_secs(5)).is_empty());
+    assert!(probe.pending.is_empty());
+}
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    parse::{ParseMode, Parsed, Parser, ProcParseError},
+    ProcRoot,
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Interrupt {
+    // the number, or a name like "NMI" or "LOC" for architecture ones
+    pub(crate) irq: String,
+    // one count per processor, a single one for ERR and MIS
+    counts: Vec<u64>,
+    pub(crate) total: u64,
+    // the interrupt controller, e.g. "IO-APIC" or "PCI-MSIX-0000:00:01.0"
+    chip: Option<String>,
+    // the handlers sharing a numbered interrupt, e.g. ["ehci_hcd:usb1", "i801_smbus"]
+    devices: Vec<String>,
+    // what an architecture interrupt is, e.g. "Local timer interrupts"
+    description: Option<String>,
+}
+
+/// /proc/interrupts, counts since boot.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Interrupts {
+    // the N of the CPUN columns, offline processors are left out
+    cpus: Vec<u32>,
+    interrupts: Vec<Interrupt>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct InterruptRate {
+    pub(crate) irq: String,
+    // over every processor
+    pub(crate) per_second: f64,
+}
+
+// what follows the counts of a numbered interrupt, the chip, the hardware
+// irq with its trigger, on some architectures a separate "Level" or "Edge",
+// and the handlers
+fn fill_numbered(interrupt: &mut Interrupt, rest: &[&str]) {
+    interrupt.chip = rest.first().map(|s| s.to_string());
+    let skip = match rest.get(2) {
+        Some(&"Level") | Some(&"Edge") => 3,
+        _ => 2,
+    };
+    let devices = rest.get(skip..).unwrap_or_default().join(" ");
+    interrupt.devices = devices
+        .split(", ")
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect();
+}
+
+fn convert(source: &str, parser: &mut Parser) -> Result<Interrupts, ProcParseError> {
+    let mut lines = source.split('\n').enumerate();
+    let mut interrupts = Interrupts::default();
+    // "           CPU0       CPU1"
+    if let Some((_, header)) = lines.next() {
+        for column in header.split_whitespace() {
+            match column
+                .strip_prefix("CPU")
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                Some(cpu) => interrupts.cpus.push(cpu),
+                None => parser.problem(1, column, "not a CPU column".to_string())?,
+            }
+        }
+    }
+    for (index, line) in lines {
+        let (irq, rest) = match line.split_once(':') {
+            Some((irq, rest)) => (irq.trim(), rest),
+            None if line.trim().is_empty() => continue,
+            None => {
+                parser.problem(index + 1, line.trim(), "no ':' in line".to_string())?;
+                continue;
+            }
+        };
+        let fields = rest.split_whitespace().collect::<Vec<&str>>();
+        let counts = fields
+            .iter()
+            .take(interrupts.cpus.len())
+            .map_while(|s| s.parse::<u64>().ok())
+            .collect::<Vec<u64>>();
+        if counts.is_empty() {
+            parser.problem(index + 1, irq, "no counts".to_string())?;
+            continue;
+        }
+        let rest = &fields[counts.len()..];
+        let mut interrupt = Interrupt {
+            irq: irq.to_string(),
+            total: counts.iter().sum(),
+            counts,
+            ..Interrupt::default()
+        };
+        if irq.parse::<u32>().is_ok() {
+            fill_numbered(&mut interrupt, rest);
+        } else if !rest.is_empty() {
+            interrupt.description = Some(rest.join(" "));
+        }
+        interrupts.interrupts.push(interrupt);
+    }
+    Ok(interrupts)
+}
+
+pub fn interrupts(proc: &ProcRoot, mode: ParseMode) -> std::io::Result<Parsed<Interrupts>> {
+    let path = proc.join("interrupts");
+    let mut parser = Parser::new(&path, mode);
+    let interrupts = convert(std::fs::read_to_string(&path)?.as_str(), &mut parser)?;
+    Ok(parser.finish(interrupts))
+}
+
+/// Rates between two `interrupts()` readings taken `seconds` apart, for
+/// the interrupts present in both.
+pub fn interrupt_rates(
+    previous: &Interrupts,
+    current: &Interrupts,
+    seconds: f64,
+) -> Vec<InterruptRate> {
+    if seconds <= 0.0 {
+        return Vec::new();
+    }
+    current
+        .interrupts
+        .iter()
+        .filter_map(|c| {
+            let p = previous.interrupts.iter().find(|p| p.irq == c.irq)?;
+            Some(InterruptRate {
+                irq: c.irq.clone(),
+                // a processor going offline takes its counts with it
+                per_second: c.total.saturating_sub(p.total) as f64 / seconds,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn interrupts_test() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let parsed = interrupts(&ProcRoot::new(fixtures.join("proc")), ParseMode::Strict).unwrap();
+    let all = parsed.data;
+    assert_eq!(all.cpus, [0, 1]);
+    let usb = all.interrupts.iter().find(|i| i.irq == "16").unwrap();
+    assert_eq!(usb.counts, [1523, 48]);
+    assert_eq!(usb.total, 1571);
+    assert_eq!(usb.chip.as_deref(), Some("IO-APIC"));
+    assert_eq!(usb.devices, ["ehci_hcd:usb1", "i801_smbus"]);
+    let nvme = all.interrupts.iter().find(|i| i.irq == "125").unwrap();
+    assert_eq!(nvme.chip.as_deref(), Some("PCI-MSIX-0000:02:00.0"));
+    assert_eq!(nvme.devices, ["nvme0q1"]);
+    let timer = all.interrupts.iter().find(|i| i.irq == "LOC").unwrap();
+    assert_eq!(timer.description.as_deref(), Some("Local timer interrupts"));
+    let err = all.interrupts.iter().find(|i| i.irq == "ERR").unwrap();
+    assert_eq!((err.counts.len(), err.description.as_ref()), (1, None));
+    // arm64, with the trigger in a column of its own
+    let mut parser = Parser::new(std::path::Path::new("/proc/interrupts"), ParseMode::Strict);
+    let gic = convert(
+        "           CPU0       CPU1\n 11:      91236      90381     GICv3  27 Level     arch_timer\n",
+        &mut parser,
+    )
+    .unwrap();
+    assert_eq!(gic.interrupts[0].devices, ["arch_timer"]);
+
+    let mut later = all.clone();
+    later.interrupts[0].total += 500;
+    let rates = interrupt_rates(&all, &later, 2.0);
+    assert_eq!(rates.len(), all.interrupts.len());
+    assert_eq!(rates[0].per_second, 250.0);
+}
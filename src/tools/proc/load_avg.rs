@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ProcRoot;
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct LoadAvg {
+    /*
+       The first three fields in this file are load
+       average figures giving the number of jobs in the run
+       queue (state R) or waiting for disk I/O (state D)
+       averaged over 1, 5, and 15 minutes.
+    */
+    pub(crate) one: Option<f64>,
+    pub(crate) five: Option<f64>,
+    pub(crate) fifteen: Option<f64>,
+    // The number of currently runnable kernel scheduling entities.
+    pub(crate) runnable: Option<u32>,
+    // The number of kernel scheduling entities that currently exist.
+    pub(crate) total: Option<u32>,
+    // The PID of the process that was most recently created.
+    last_pid: Option<u32>,
+}
+
+// e.g. "0.20 0.18 0.12 1/80 11206"
+fn convert(source: &str) -> LoadAvg {
+    let mut fields = source.split_whitespace();
+    let mut load = || fields.next().and_then(|s| s.parse::<f64>().ok());
+    let (one, five, fifteen) = (load(), load(), load());
+    let (runnable, total) = match fields.next().and_then(|s| s.split_once('/')) {
+        Some((runnable, total)) => (runnable.parse::<u32>().ok(), total.parse::<u32>().ok()),
+        None => (None, None),
+    };
+    LoadAvg {
+        one,
+        five,
+        fifteen,
+        runnable,
+        total,
+        last_pid: fields.next().and_then(|s| s.parse::<u32>().ok()),
+    }
+}
+
+pub fn load_avg(proc: &ProcRoot) -> std::io::Result<LoadAvg> {
+    Ok(convert(
+        std::fs::read_to_string(proc.join("loadavg"))?.as_str(),
+    ))
+}
+
+#[test]
+fn load_avg_test() {
+    let load = convert("0.20 0.18 0.12 1/80 11206\n");
+    assert_eq!(
+        (load.one, load.five, load.fifteen),
+        (Some(0.2), Some(0.18), Some(0.12))
+    );
+    assert_eq!((load.runnable, load.total), (Some(1), Some(80)));
+    assert_eq!(load.last_pid, Some(11206));
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let load = load_avg(&ProcRoot::new(fixtures.join("proc"))).unwrap();
+    assert_eq!((load.runnable, load.total), (Some(1), Some(80)));
+}
@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use crate::tools::{
-    logger::log::{log, LogLevel},
-    BitType,
+    parse::{number, ParseMode, Parsed, Parser, ProcParseError},
+    BitType, ProcRoot,
 };
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -12,24 +12,24 @@ pub struct MemoInfo {
        Total usable RAM (i.e., physical RAM minus a few
        reserved bits and the kernel binary code).
     */
-    mem_total: Option<BitType>,
+    pub(crate) mem_total: Option<BitType>,
     //The sum of LowFree+HighFree.
-    mem_free: Option<BitType>,
+    pub(crate) mem_free: Option<BitType>,
     /*
         An estimate of how much memory is available for
         starting new applications, without swapping.
     */
-    mem_available: Option<BitType>,
+    pub(crate) mem_available: Option<BitType>,
     /*
        Relatively temporary storage for raw disk blocks
        that shouldn't get tremendously large (20 MB or so).
     */
-    buffers: Option<BitType>,
+    pub(crate) buffers: Option<BitType>,
     /*
         In-memory cache for files read from the disk (the
         page cache).  Doesn't include SwapCached.
     */
-    cached: Option<BitType>,
+    pub(crate) cached: Option<BitType>,
     /*
        Memory that once was swapped out, is swapped back
        in but still also is in the swap file.  (If memory
@@ -114,11 +114,11 @@ pub struct MemoInfo {
     /*
        Total amount of swap space available.
     */
-    swap_total: Option<BitType>,
+    pub(crate) swap_total: Option<BitType>,
     /*
        Amount of swap space that is currently unused.
     */
-    swap_free: Option<BitType>,
+    pub(crate) swap_free: Option<BitType>,
     /*
        Memory which is waiting to get written back to the
        disk.
@@ -307,12 +307,12 @@ pub struct MemoInfo {
        (CONFIG_HUGETLB_PAGE is required.)  The size of the
        pool of huge pages.
     */
-    huge_pages_total: Option<BitType>,
+    huge_pages_total: Option<u64>,
     /*
         (CONFIG_HUGETLB_PAGE is required.)  The number of
         huge pages in the pool that are not yet allocated.
     */
-    huge_pages_free: Option<BitType>,
+    huge_pages_free: Option<u64>,
     /*
        (since Linux 2.6.17)
        (CONFIG_HUGETLB_PAGE is required.)  This is the
@@ -323,7 +323,7 @@ pub struct MemoInfo {
        allocate a huge page from the pool of huge pages at
        fault time.
     */
-    huge_pages_rsvd: Option<BitType>,
+    huge_pages_rsvd: Option<u64>,
     /*
        (since Linux 2.6.24)
        (CONFIG_HUGETLB_PAGE is required.)  This is the
@@ -332,7 +332,7 @@ pub struct MemoInfo {
        surplus huge pages is controlled by
        /proc/sys/vm/nr_overcommit_hugepages.
     */
-    huge_pages_surp: Option<BitType>,
+    huge_pages_surp: Option<u64>,
     /*
        (CONFIG_HUGETLB_PAGE is required.)  The size of
        huge pages.
@@ -372,88 +372,248 @@ pub struct MemoInfo {
     direct_map1_g: Option<BitType>,
 }
 
-pub fn mem_info() -> std::io::Result<MemoInfo> {
-    let path = "/proc/meminfo";
+// where the value of `name` goes, for the sizes
+fn field<'a>(mem_info: &'a mut MemoInfo, name: &str) -> Option<&'a mut Option<BitType>> {
+    Some(match name {
+        "MemTotal" => &mut mem_info.mem_total,
+        "MemFree" => &mut mem_info.mem_free,
+        "MemAvailable" => &mut mem_info.mem_available,
+        "Buffers" => &mut mem_info.buffers,
+        "Cached" => &mut mem_info.cached,
+        "SwapCached" => &mut mem_info.swap_cached,
+        "Active" => &mut mem_info.active,
+        "Inactive" => &mut mem_info.inactive,
+        "Active(anon)" => &mut mem_info.active_anon,
+        "Inactive(anon)" => &mut mem_info.inactive_anon,
+        "Active(file)" => &mut mem_info.active_file,
+        "Inactive(file)" => &mut mem_info.inactive_file,
+        "Unevictable" => &mut mem_info.unevictable,
+        "Mlocked" => &mut mem_info.mlocked,
+        "HighTotal" => &mut mem_info.high_total,
+        "HighFree" => &mut mem_info.high_free,
+        "LowTotal" => &mut mem_info.low_total,
+        "LowFree" => &mut mem_info.low_free,
+        "MmapCopy" => &mut mem_info.mmap_copy,
+        "SwapTotal" => &mut mem_info.swap_total,
+        "SwapFree" => &mut mem_info.swap_free,
+        "Dirty" => &mut mem_info.dirty,
+        "Writeback" => &mut mem_info.write_back,
+        "AnonPages" => &mut mem_info.anon_pages,
+        "Mapped" => &mut mem_info.mapped,
+        "Shmem" => &mut mem_info.shmem,
+        "KReclaimable" => &mut mem_info.k_reclaimable,
+        "Slab" => &mut mem_info.slab,
+        "SReclaimable" => &mut mem_info.s_reclaimable,
+        "SUnreclaim" => &mut mem_info.s_unreclaim,
+        "KernelStack" => &mut mem_info.kernel_stack,
+        "PageTables" => &mut mem_info.page_tables,
+        "Quicklists" => &mut mem_info.quicklists,
+        "NFS_Unstable" => &mut mem_info.n_f_s_unstable,
+        "Bounce" => &mut mem_info.bounce,
+        "WritebackTmp" => &mut mem_info.writeback_tmp,
+        "CommitLimit" => &mut mem_info.commit_limit,
+        "HardwareCorrupted" => &mut mem_info.hardware_corrupted,
+        "Committed_AS" => &mut mem_info.committed_a_s,
+        "VmallocTotal" => &mut mem_info.vmalloc_total,
+        "VmallocUsed" => &mut mem_info.vmalloc_used,
+        "VmallocChunk" => &mut mem_info.vmalloc_chunk,
+        "LazyFree" => &mut mem_info.lazy_free,
+        "AnonHugePages" => &mut mem_info.anon_huge_pages,
+        "ShmemHugePages" => &mut mem_info.shmem_huge_pages,
+        "ShmemPmdMapped" => &mut mem_info.shmem_pmd_mapped,
+        "CmaTotal" => &mut mem_info.cma_total,
+        "CmaFree" => &mut mem_info.cma_free,
+        "Hugepagesize" => &mut mem_info.huge_page_size,
+        "DirectMap4k" => &mut mem_info.direct_map4k,
+        "DirectMap4M" => &mut mem_info.direct_map4m,
+        "DirectMap2M" => &mut mem_info.direct_map2_m,
+        "DirectMap1G" => &mut mem_info.direct_map1_g,
+        "Hugetlb" => &mut mem_info.hugetlb,
+        "Percpu" => &mut mem_info.percpu,
+        "FileHugePages" => &mut mem_info.file_huge_pages,
+        "FilePmdMapped" => &mut mem_info.file_pmd_mapped,
+        _ => return None,
+    })
+}
+
+/// What free(1) shows, from one `MemoInfo`.  A field is None
+/// when the kernel did not report what it is computed from.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct MemorySummary {
+    total: Option<BitType>,
+    free: Option<BitType>,
+    // total - available, as procps-ng 4 does
+    pub(crate) used: Option<BitType>,
+    // MemAvailable, or free + buffers + cache before Linux 3.14
+    pub(crate) available: Option<BitType>,
+    buffers: Option<BitType>,
+    // Cached + SReclaimable, the buff/cache column without Buffers
+    cache: Option<BitType>,
+    // Shmem, tmpfs included, counted in cache as well
+    shared: Option<BitType>,
+    swap_total: Option<BitType>,
+    pub(crate) swap_used: Option<BitType>,
+    swap_free: Option<BitType>,
+    // of total and swap_total, in percent
+    used_percent: Option<f64>,
+    swap_used_percent: Option<f64>,
+    /*
+       Committed_AS against CommitLimit.  Above 100 percent the
+       system promised more than it can back, which only fails
+       with vm.overcommit_memory=2.
+    */
+    committed: Option<BitType>,
+    commit_limit: Option<BitType>,
+    committed_percent: Option<f64>,
+}
+
+#[inline]
+fn percent(part: Option<BitType>, whole: Option<BitType>) -> Option<f64> {
+    match (part, whole) {
+        (Some(part), Some(whole)) if whole.bytes() > 0 => {
+            Some(part.bytes() as f64 * 100.0 / whole.bytes() as f64)
+        }
+        _ => None,
+    }
+}
+
+pub fn memory_summary(mem: &MemoInfo) -> MemorySummary {
+    let (total, free, buffers) = (mem.mem_total, mem.mem_free, mem.buffers);
+    let cache = mem
+        .cached
+        .map(|c| c + mem.s_reclaimable.unwrap_or_default());
+    let available = mem
+        .mem_available
+        .or_else(|| Some(free? + buffers? + cache?));
+    let used = match (total, available) {
+        (Some(total), Some(available)) => Some(total - available),
+        _ => None,
+    };
+    let (swap_total, swap_free) = (mem.swap_total, mem.swap_free);
+    let swap_used = match (swap_total, swap_free) {
+        (Some(total), Some(free)) => Some(total - free),
+        _ => None,
+    };
+    let (committed, commit_limit) = (mem.committed_a_s, mem.commit_limit);
+    MemorySummary {
+        total,
+        free,
+        used,
+        available,
+        buffers,
+        cache,
+        shared: mem.shmem,
+        swap_total,
+        swap_used,
+        swap_free,
+        used_percent: percent(used, total),
+        swap_used_percent: percent(swap_used, swap_total),
+        committed,
+        commit_limit,
+        committed_percent: percent(committed, commit_limit),
+    }
+}
+
+fn convert(source: &str, parser: &mut Parser) -> Result<MemoInfo, ProcParseError> {
     let mut mem_info = MemoInfo::default();
-    let file_content = std::fs::read_to_string(path)?;
-    file_content
-        .split("\n")
-        .map(|s| s.split(":"))
-        .map(|mut s| -> Option<(&str, &str)> { Some((s.next()?.trim(), s.next()?.trim())) })
-        .filter(|s| s.is_some())
-        .map(|s| s.unwrap())
-        .for_each(|(s1, s): (&str, &str)| match s1 {
-            "MemTotal" => mem_info.mem_total = BitType::new(s),
-            "MemFree" => mem_info.mem_free = BitType::new(s),
-            "MemAvailable" => mem_info.mem_available = BitType::new(s),
-            "Buffers" => mem_info.buffers = BitType::new(s),
-            "Cached" => mem_info.cached = BitType::new(s),
-            "SwapCached" => mem_info.swap_cached = BitType::new(s),
-            "Active" => mem_info.active = BitType::new(s),
-            "Inactive" => mem_info.inactive = BitType::new(s),
-            "Active(anon)" => mem_info.active_anon = BitType::new(s),
-            "Inactive(anon)" => mem_info.inactive = BitType::new(s),
-            "Active(file)" => mem_info.active_file = BitType::new(s),
-            "Inactive(file)" => mem_info.inactive_file = BitType::new(s),
-            "Unevictable" => mem_info.unevictable = BitType::new(s),
-            "Mlocked" => mem_info.mlocked = BitType::new(s),
-            "HighTotal" => mem_info.high_total = BitType::new(s),
-            "HighFree" => mem_info.high_free = BitType::new(s),
-            "LowTotal" => mem_info.low_total = BitType::new(s),
-            "LowFree" => mem_info.low_free = BitType::new(s),
-            "MmapCopy" => mem_info.mmap_copy = BitType::new(s),
-            "SwapTotal" => mem_info.swap_total = BitType::new(s),
-            "SwapFree" => mem_info.swap_free = BitType::new(s),
-            "Dirty" => mem_info.dirty = BitType::new(s),
-            "Writeback" => mem_info.write_back = BitType::new(s),
-            "AnonPages" => mem_info.anon_pages = BitType::new(s),
-            "Mapped" => mem_info.mapped = BitType::new(s),
-            "Shmem" => mem_info.shmem = BitType::new(s),
-            "KReclaimable" => mem_info.k_reclaimable = BitType::new(s),
-            "Slab" => mem_info.slab = BitType::new(s),
-            "SReclaimable" => mem_info.s_reclaimable = BitType::new(s),
-            "SUnreclaim" => mem_info.s_unreclaim = BitType::new(s),
-            "KernelStack" => mem_info.kernel_stack = BitType::new(s),
-            "PageTables" => mem_info.page_tables = BitType::new(s),
-            "Quicklists" => mem_info.quicklists = BitType::new(s),
-            "NFS_Unstable" => mem_info.n_f_s_unstable = BitType::new(s),
-            "Bounce" => mem_info.bounce = BitType::new(s),
-            "WritebackTmp" => mem_info.writeback_tmp = BitType::new(s),
-            "CommitLimit" => mem_info.commit_limit = BitType::new(s),
-            "HardwareCorrupted" => mem_info.hardware_corrupted = BitType::new(s),
-            "Committed_AS" => mem_info.committed_a_s = BitType::new(s),
-            "VmallocTotal" => mem_info.vmalloc_total = BitType::new(s),
-            "VmallocUsed" => mem_info.vmalloc_used = BitType::new(s),
-            "VmallocChunk" => mem_info.vmalloc_chunk = BitType::new(s),
-            "LazyFree" => mem_info.lazy_free = BitType::new(s),
-            "AnonHugePages" => mem_info.anon_huge_pages = BitType::new(s),
-            "ShmemHugePages" => mem_info.shmem_huge_pages = BitType::new(s),
-            "ShmemPmdMapped" => mem_info.shmem_pmd_mapped = BitType::new(s),
-            "CmaTotal" => mem_info.cma_total = BitType::new(s),
-            "CmaFree" => mem_info.cma_free = BitType::new(s),
-            "HugePages_Total" => mem_info.huge_pages_total = BitType::new(s),
-            "HugePages_Free" => mem_info.huge_pages_free = BitType::new(s),
-            "HugePages_Rsvd" => mem_info.huge_pages_rsvd = BitType::new(s),
-            "HugePages_Surp" => mem_info.huge_pages_surp = BitType::new(s),
-            "Hugepagesize" => mem_info.huge_page_size = BitType::new(s),
-            "DirectMap4k" => mem_info.direct_map4k = BitType::new(s),
-            "DirectMap4M" => mem_info.direct_map4m = BitType::new(s),
-            "DirectMap2M" => mem_info.direct_map2_m = BitType::new(s),
-            "DirectMap1G" => mem_info.direct_map1_g = BitType::new(s),
-            "Hugetlb" => mem_info.hugetlb = BitType::new(s),
-            "Precpu" => mem_info.percpu = BitType::new(s),
-            "FileHugePages" => mem_info.file_huge_pages = BitType::new(s),
-            "FilePmdMapped" => mem_info.file_pmd_mapped = BitType::new(s),
-            _ => {
-                log(LogLevel::Warn(
-                    std::format!("{} not support, value : {}", s1, s).as_str(),
-                ));
+    for (index, line) in source.split('\n').enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => {
+                parser.problem(index + 1, line.trim(), "no ':' in line".to_string())?;
+                continue;
             }
-        });
+        };
+        let result = match name {
+            // counts of huge pages, without a unit
+            "HugePages_Total" => number(value).map(|v| mem_info.huge_pages_total = Some(v)),
+            "HugePages_Free" => number(value).map(|v| mem_info.huge_pages_free = Some(v)),
+            "HugePages_Rsvd" => number(value).map(|v| mem_info.huge_pages_rsvd = Some(v)),
+            "HugePages_Surp" => number(value).map(|v| mem_info.huge_pages_surp = Some(v)),
+            _ => match field(&mut mem_info, name) {
+                Some(slot) => BitType::from_kernel(value).map(|value| *slot = Some(value)),
+                None => Err("unknown field".to_string()),
+            },
+        };
+        if let Err(reason) = result {
+            parser.problem(index + 1, name, reason)?;
+        }
+    }
     Ok(mem_info)
 }
 
+pub fn mem_info(proc: &ProcRoot, mode: ParseMode) -> std::io::Result<Parsed<MemoInfo>> {
+    let path = proc.join("meminfo");
+    let mut parser = Parser::new(&path, mode);
+    let mem_info = convert(std::fs::read_to_string(&path)?.as_str(), &mut parser)?;
+    Ok(parser.finish(mem_info))
+}
+
 #[test]
-fn test_mem_info() {
-    assert!(mem_info().is_ok());
+fn mem_info_test() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    // every field of a 5.15 kernel is known
+    let mem = mem_info(&ProcRoot::new(fixtures.join("proc")), ParseMode::Strict)
+        .unwrap()
+        .data;
+    let bytes = |b: &Option<BitType>| b.map(|b| b.bytes());
+    assert_eq!(bytes(&mem.mem_total), Some(16_265_236 * 1024));
+    assert_eq!(bytes(&mem.mem_available), Some(11_887_944 * 1024));
+    assert_eq!(bytes(&mem.swap_free), Some(2_097_148 * 1024));
+    assert_eq!(bytes(&mem.direct_map1_g), Some(5_242_880 * 1024));
+    assert_eq!(bytes(&mem.percpu), Some(9088 * 1024));
+    // counts without a unit
+    assert_eq!(
+        (
+            mem.huge_pages_total,
+            mem.huge_pages_free,
+            mem.huge_pages_rsvd
+        ),
+        (Some(4), Some(3), Some(1))
+    );
+    assert_eq!(bytes(&mem.hugetlb), Some(8192 * 1024));
+    assert_eq!(bytes(&mem.inactive), Some(2_477_808 * 1024));
+    assert_eq!(bytes(&mem.inactive_anon), Some(48_084 * 1024));
+    let summary = memory_summary(&mem);
+    assert_eq!(bytes(&summary.used), Some(4_377_292 * 1024));
+    assert_eq!(bytes(&summary.cache), Some((3_528_676 + 250_248) * 1024));
+    assert_eq!(bytes(&summary.buffers), Some(412_540 * 1024));
+    assert_eq!(bytes(&summary.shared), Some(127_416 * 1024));
+    assert_eq!(
+        (bytes(&summary.swap_used), summary.swap_used_percent),
+        (Some(0), Some(0.0))
+    );
+    assert_eq!(
+        summary.used_percent.map(|p| (p * 100.0).round() / 100.0),
+        Some(26.91)
+    );
+    // overcommitted
+    assert_eq!(
+        summary
+            .committed_percent
+            .map(|p| (p * 100.0).round() / 100.0),
+        Some(105.7)
+    );
+    // before MemAvailable, and without swap
+    let old = convert(
+        "MemTotal: 1000 kB\nMemFree: 100 kB\nBuffers: 50 kB\nCached: 250 kB\nSwapTotal: 0 kB\nSwapFree: 0 kB\n",
+        &mut Parser::new(std::path::Path::new("/proc/meminfo"), ParseMode::Strict),
+    )
+    .unwrap();
+    let summary = memory_summary(&old);
+    assert_eq!(bytes(&summary.available), Some(400 * 1024));
+    assert_eq!(bytes(&summary.used), Some(600 * 1024));
+    assert!(summary.swap_used_percent.is_none());
+    let mut parser = Parser::new(std::path::Path::new("/proc/meminfo"), ParseMode::Lenient);
+    let mem = convert(
+        "MemTotal: 99999999999999999999 kB\nMemFree: 1024 kB\n",
+        &mut parser,
+    )
+    .unwrap();
+    assert!(mem.mem_total.is_none());
+    assert_eq!(bytes(&mem.mem_free), Some(1024 * 1024));
+    assert_eq!(parser.finish(()).warnings[0].field, "MemTotal");
 }
@@ -1,3 +1,24 @@
+pub mod cgroup;
+pub mod cpu_freq;
 pub mod cpu_info;
 pub mod cpu_stat;
+pub mod cpu_topology;
+pub mod cpu_usage;
+pub mod disk_stats;
+pub mod filesystems;
+pub mod interrupts;
+pub mod load_avg;
 pub mod mem_info;
+pub mod net_dev;
+pub mod parse;
+pub mod pid_detail;
+pub mod pid_info;
+pub mod pressure;
+mod root;
+pub mod sensors;
+pub mod sockets;
+pub(crate) mod sysfs;
+pub mod system_summary;
+pub mod vm_stat;
+
+pub use root::{AccountFiles, ProcRoot, SysRoot};
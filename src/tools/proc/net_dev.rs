@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{ProcRoot, SysRoot};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct NetInterface {
+    name: String,
+    /*
+       RFC 2863 operational state: up, down, dormant,
+       lowerlayerdown, notpresent, testing or unknown.
+    */
+    operstate: Option<String>,
+    /*
+       Link speed in Mbits/sec, `None` when the driver does
+       not know it (reads fail or report -1).
+    */
+    speed: Option<u32>,
+    // Maximum transmission unit in bytes.
+    mtu: Option<u32>,
+    // Hardware address, e.g. 00:11:22:33:44:55.
+    address: Option<String>,
+    // Receive columns of /proc/net/dev.
+    rx_bytes: Option<u64>,
+    rx_packets: Option<u64>,
+    rx_errors: Option<u64>,
+    rx_dropped: Option<u64>,
+    rx_fifo: Option<u64>,
+    rx_frame: Option<u64>,
+    rx_compressed: Option<u64>,
+    rx_multicast: Option<u64>,
+    // Transmit columns of /proc/net/dev.
+    tx_bytes: Option<u64>,
+    tx_packets: Option<u64>,
+    tx_errors: Option<u64>,
+    tx_dropped: Option<u64>,
+    tx_fifo: Option<u64>,
+    tx_collisions: Option<u64>,
+    tx_carrier: Option<u64>,
+    tx_compressed: Option<u64>,
+}
+
+/// Per second rates of one interface between two readings.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct NetRate {
+    pub name: String,
+    pub rx_bytes: f64,
+    pub rx_packets: f64,
+    pub rx_errors: f64,
+    pub rx_dropped: f64,
+    pub tx_bytes: f64,
+    pub tx_packets: f64,
+    pub tx_errors: f64,
+    pub tx_dropped: f64,
+}
+
+// "  eth0: 4068 61 0 ..." after the two header lines
+fn convert_line(line: &str) -> Option<NetInterface> {
+    let (name, counters) = line.split_once(':')?;
+    let values = counters
+        .split_whitespace()
+        .map(|s| s.parse::<u64>().ok())
+        .collect::<Vec<Option<u64>>>();
+    let value = |i: usize| values.get(i).copied().flatten();
+    Some(NetInterface {
+        name: name.trim().to_string(),
+        rx_bytes: value(0),
+        rx_packets: value(1),
+        rx_errors: value(2),
+        rx_dropped: value(3),
+        rx_fifo: value(4),
+        rx_frame: value(5),
+        rx_compressed: value(6),
+        rx_multicast: value(7),
+        tx_bytes: value(8),
+        tx_packets: value(9),
+        tx_errors: value(10),
+        tx_dropped: value(11),
+        tx_fifo: value(12),
+        tx_collisions: value(13),
+        tx_carrier: value(14),
+        tx_compressed: value(15),
+        ..NetInterface::default()
+    })
+}
+
+#[inline]
+fn read_attribute(path: &Path) -> Option<String> {
+    Some(std::fs::read_to_string(path).ok()?.trim().to_string())
+}
+
+// reading speed of a link that is down fails with EINVAL
+fn fill_sysfs(interface: &mut NetInterface, sys_class_net: &Path) {
+    let path = sys_class_net.join(interface.name.as_str());
+    interface.operstate = read_attribute(&path.join("operstate"));
+    interface.speed = read_attribute(&path.join("speed")).and_then(|s| s.parse::<u32>().ok());
+    interface.mtu = read_attribute(&path.join("mtu")).and_then(|s| s.parse::<u32>().ok());
+    interface.address = read_attribute(&path.join("address"));
+}
+
+pub fn net_dev(proc: &ProcRoot, sys: &SysRoot) -> std::io::Result<Vec<NetInterface>> {
+    let sys_class_net = sys.join("class/net");
+    Ok(std::fs::read_to_string(proc.namespaced("net/dev"))?
+        .split('\n')
+        .skip(2)
+        .filter_map(convert_line)
+        .map(|mut interface| {
+            fill_sysfs(&mut interface, &sys_class_net);
+            interface
+        })
+        .collect())
+}
+
+/// Rates between two `net_dev()` readings taken `seconds` apart, for the
+/// interfaces present in both.  Counters that went backwards, e.g. after
+/// the interface was recreated, count as 0.
+pub fn net_rates(
+    previous: &[NetInterface],
+    current: &[NetInterface],
+    seconds: f64,
+) -> Vec<NetRate> {
+    if seconds <= 0.0 {
+        return Vec::new();
+    }
+    current
+        .iter()
+        .filter_map(|c| {
+            let p = previous.iter().find(|p| p.name == c.name)?;
+            let rate = |p: Option<u64>, c: Option<u64>| {
+                c.unwrap_or(0).saturating_sub(p.unwrap_or(0)) as f64 / seconds
+            };
+            Some(NetRate {
+                name: c.name.clone(),
+                rx_bytes: rate(p.rx_bytes, c.rx_bytes),
+                rx_packets: rate(p.rx_packets, c.rx_packets),
+                rx_errors: rate(p.rx_errors, c.rx_errors),
+                rx_dropped: rate(p.rx_dropped, c.rx_dropped),
+                tx_bytes: rate(p.tx_bytes, c.tx_bytes),
+                tx_packets: rate(p.tx_packets, c.tx_packets),
+                tx_errors: rate(p.tx_errors, c.tx_errors),
+                tx_dropped: rate(p.tx_dropped, c.tx_dropped),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn net_dev_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let interfaces = net_dev(
+        &ProcRoot::new(fixtures.join("proc")),
+        &SysRoot::new(fixtures.join("sys")),
+    )
+    .unwrap();
+    assert_eq!(
+        interfaces
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect::<Vec<&str>>(),
+        ["lo", "enp3s0", "wlan0"]
+    );
+    let wired = &interfaces[1];
+    assert_eq!(wired.rx_bytes, Some(9132845571));
+    assert_eq!(wired.rx_dropped, Some(1022));
+    assert_eq!(wired.rx_multicast, Some(31870));
+    assert_eq!(wired.tx_packets, Some(3124871));
+    assert_eq!((wired.speed, wired.mtu), (Some(1000), Some(1500)));
+    assert_eq!(wired.operstate.as_deref(), Some("up"));
+    assert_eq!(wired.address.as_deref(), Some("3c:7c:3f:1e:a2:04"));
+    // no speed file for loopback, -1 for a wireless link
+    assert!(interfaces[0].speed.is_none());
+    assert!(interfaces[2].speed.is_none());
+    let mut later = interfaces.clone();
+    later[1].rx_bytes = Some(9132845571 + 2_000_000);
+    later[2].tx_dropped = Some(5);
+    let rates = net_rates(&interfaces, &later, 2.0);
+    assert_eq!(rates[1].rx_bytes, 1_000_000.0);
+    assert_eq!(rates[2].tx_dropped, 2.0);
+    assert_eq!(rates[0].tx_bytes, 0.0);
+}
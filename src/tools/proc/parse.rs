@@ -0,0 +1,126 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// A line of a /proc file that could not be understood.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ProcParseError {
+    // e.g. "/proc/meminfo"
+    pub file: String,
+    // counted from 1
+    pub line: usize,
+    pub field: String,
+    pub reason: String,
+}
+
+impl Display for ProcParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.file, self.line, self.field, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ProcParseError {}
+
+impl From<ProcParseError> for std::io::Error {
+    fn from(e: ProcParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseMode {
+    // the first unknown or invalid field fails the whole file
+    Strict,
+    // such fields are skipped and reported in `Parsed::warnings`
+    Lenient,
+}
+
+/// What a parser made of a file, and what it had to skip doing so.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Parsed<T> {
+    pub data: T,
+    pub warnings: Vec<ProcParseError>,
+}
+
+/// Collects the problems of one file according to its `ParseMode`.
+pub(crate) struct Parser {
+    file: String,
+    mode: ParseMode,
+    warnings: Vec<ProcParseError>,
+}
+
+impl Parser {
+    pub(crate) fn new(file: &std::path::Path, mode: ParseMode) -> Self {
+        Parser {
+            file: file.display().to_string(),
+            mode,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// An error in strict mode, a warning otherwise.
+    pub(crate) fn problem(
+        &mut self,
+        line: usize,
+        field: &str,
+        reason: String,
+    ) -> Result<(), ProcParseError> {
+        let error = ProcParseError {
+            file: self.file.clone(),
+            line,
+            field: field.to_string(),
+            reason,
+        };
+        match self.mode {
+            ParseMode::Strict => Err(error),
+            ParseMode::Lenient => {
+                self.warnings.push(error);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn finish<T>(self, data: T) -> Parsed<T> {
+        Parsed {
+            data,
+            warnings: self.warnings,
+        }
+    }
+}
+
+// the reason is the one of `FromStr`, e.g. "invalid digit found in string"
+#[inline]
+pub(crate) fn number<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value
+        .parse::<T>()
+        .map_err(|e| std::format!("{:?}: {}", value, e))
+}
+
+#[test]
+fn parser_test() {
+    let path = std::path::Path::new("/proc/meminfo");
+    let mut lenient = Parser::new(path, ParseMode::Lenient);
+    assert!(lenient
+        .problem(3, "Bogus", "unknown field".to_string())
+        .is_ok());
+    let parsed = lenient.finish(42);
+    assert_eq!(parsed.data, 42);
+    assert_eq!(parsed.warnings[0].line, 3);
+    let mut strict = Parser::new(path, ParseMode::Strict);
+    let error = strict
+        .problem(7, "MemTotal", number::<u64>("12x").unwrap_err())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        r#"/proc/meminfo:7: MemTotal: "12x": invalid digit found in string"#
+    );
+    let error: std::io::Error = error.into();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
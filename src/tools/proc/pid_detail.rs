@@ -0,0 +1,338 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    pid_info::{process_info, read_users, ProcessInfo},
+    AccountFiles, ProcRoot,
+};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct FileDescriptor {
+    // The file descriptor number, the name of the entry in fd/.
+    fd: u32,
+    /*
+       Target of the symbolic link: a path, or a pseudo
+       file such as `socket:[inode]`, `pipe:[inode]` or
+       `anon_inode:[eventfd]`.
+    */
+    target: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ResourceLimit {
+    // e.g. "Max open files"
+    name: String,
+    // Soft limit, `None` when unlimited.
+    soft: Option<u64>,
+    // Hard limit (ceiling for the soft limit), `None` when unlimited.
+    hard: Option<u64>,
+    // e.g. "bytes", "files", missing for priorities
+    unit: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ProcessIo {
+    /*
+       The number of bytes which this task has caused to
+       be read from storage.  This is simply the sum of
+       bytes which this process passed to read(2) and
+       similar system calls.
+    */
+    rchar: Option<u64>,
+    /*
+       The number of bytes which this task has caused, or
+       shall cause to be written to disk.
+    */
+    wchar: Option<u64>,
+    // Attempt to count the number of read I/O operations.
+    syscr: Option<u64>,
+    // Attempt to count the number of write I/O operations.
+    syscw: Option<u64>,
+    /*
+       Attempt to count the number of bytes which this
+       process really did cause to be fetched from the
+       storage layer.
+    */
+    read_bytes: Option<u64>,
+    /*
+       Attempt to count the number of bytes which this
+       process caused to be sent to the storage layer.
+    */
+    write_bytes: Option<u64>,
+    /*
+       Bytes which were not written after all because of
+       truncating pagecache.
+    */
+    cancelled_write_bytes: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ProcessDetail {
+    info: ProcessInfo,
+    // Current working directory, the cwd link.
+    cwd: Option<String>,
+    // Actual pathname of the executed command, the exe link.
+    exe: Option<String>,
+    /*
+       Open file descriptors.  `None` when fd/ can not be
+       read, e.g. the process belongs to another user.
+    */
+    fds: Option<Vec<FileDescriptor>>,
+    limits: Vec<ResourceLimit>,
+    io: Option<ProcessIo>,
+    /*
+       Only read when asked for, with the code of the Owner.
+       Secret looking values and the credentials of URLs are
+       redacted.
+    */
+    environ: Option<Vec<(String, String)>>,
+}
+
+// names of variables that usually hold credentials, e.g. MYSQL_PWD or SENTRY_DSN
+static SECRET_NAMES: [&str; 8] = [
+    "KEY",
+    "TOKEN",
+    "SECRET",
+    "PASS",
+    "_PWD",
+    "CREDENTIAL",
+    "AUTH",
+    "DSN",
+];
+
+#[inline]
+fn read_link(path: &Path) -> Option<String> {
+    Some(std::fs::read_link(path).ok()?.to_string_lossy().to_string())
+}
+
+fn file_descriptors(fd_path: &Path) -> std::io::Result<Vec<FileDescriptor>> {
+    let mut fds = std::fs::read_dir(fd_path)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some(FileDescriptor {
+                fd: entry.file_name().to_str()?.parse::<u32>().ok()?,
+                target: read_link(&entry.path()),
+            })
+        })
+        .collect::<Vec<FileDescriptor>>();
+    fds.sort_by_key(|fd| fd.fd);
+    Ok(fds)
+}
+
+#[inline]
+fn limit_value(value: &str) -> Option<u64> {
+    match value {
+        "unlimited" => None,
+        v => v.parse::<u64>().ok(),
+    }
+}
+
+// the table is column aligned, take the column offsets from the header
+fn convert_limits(source: &str) -> Vec<ResourceLimit> {
+    let mut lines = source.split('\n');
+    let header = match lines.next() {
+        Some(h) => h,
+        None => return Vec::new(),
+    };
+    let (soft, hard, unit) = match (
+        header.find("Soft Limit"),
+        header.find("Hard Limit"),
+        header.find("Units"),
+    ) {
+        (Some(s), Some(h), Some(u)) => (s, h, u),
+        _ => return Vec::new(),
+    };
+    let column = |line: &str, from: usize, to: usize| -> String {
+        line.get(from..to.min(line.len()))
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    lines
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let unit_value = column(line, unit, line.len());
+            ResourceLimit {
+                name: column(line, 0, soft),
+                soft: limit_value(column(line, soft, hard).as_str()),
+                hard: limit_value(column(line, hard, unit).as_str()),
+                unit: if unit_value.is_empty() {
+                    None
+                } else {
+                    Some(unit_value)
+                },
+            }
+        })
+        .collect()
+}
+
+fn convert_io(source: &str) -> ProcessIo {
+    let mut io = ProcessIo::default();
+    source
+        .split('\n')
+        .map(|s| s.split(':'))
+        .filter_map(|mut s| -> Option<(&str, u64)> {
+            Some((s.next()?.trim(), s.next()?.trim().parse::<u64>().ok()?))
+        })
+        .for_each(|(name, value)| match name {
+            "rchar" => io.rchar = Some(value),
+            "wchar" => io.wchar = Some(value),
+            "syscr" => io.syscr = Some(value),
+            "syscw" => io.syscw = Some(value),
+            "read_bytes" => io.read_bytes = Some(value),
+            "write_bytes" => io.write_bytes = Some(value),
+            "cancelled_write_bytes" => io.cancelled_write_bytes = Some(value),
+            _ => {}
+        });
+    io
+}
+
+// the user:password@ of every URL in `value`, e.g. REDIS_URL or a list of them
+fn mask_userinfo(value: &str) -> String {
+    let mut masked = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(scheme) = rest.find("://") {
+        let (head, tail) = rest.split_at(scheme + 3);
+        masked.push_str(head);
+        let authority = tail
+            .find(|c: char| c == '/' || c == '?' || c == '#' || c.is_whitespace() || c == ',')
+            .unwrap_or(tail.len());
+        match tail[..authority].rfind('@') {
+            Some(at) => {
+                masked.push_str("[REDACTED]");
+                rest = &tail[at..];
+            }
+            None => rest = tail,
+        }
+    }
+    masked.push_str(rest);
+    masked
+}
+
+// one variable that is not UTF-8 does not hide the others
+fn convert_environ(source: &[u8]) -> Vec<(String, String)> {
+    source
+        .split(|b| *b == b'\0')
+        .map(String::from_utf8_lossy)
+        .filter_map(|s| {
+            let (name, value) = s.split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .map(|(name, value)| {
+            let upper = name.to_ascii_uppercase();
+            let value = if SECRET_NAMES.iter().any(|secret| upper.contains(secret)) {
+                "[REDACTED]".to_string()
+            } else {
+                mask_userinfo(value.as_str())
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+fn process_detail(
+    proc_path: &Path,
+    passwd_path: &Path,
+    pid: u32,
+    environ: bool,
+) -> std::io::Result<ProcessDetail> {
+    // NotFound from here means the process is gone
+    let info = process_info(proc_path, pid, &read_users(passwd_path))?;
+    let pid_path = proc_path.join(pid.to_string());
+    Ok(ProcessDetail {
+        info,
+        cwd: read_link(&pid_path.join("cwd")),
+        exe: read_link(&pid_path.join("exe")),
+        fds: file_descriptors(&pid_path.join("fd")).ok(),
+        limits: std::fs::read_to_string(pid_path.join("limits"))
+            .map(|s| convert_limits(s.as_str()))
+            .unwrap_or_default(),
+        io: std::fs::read_to_string(pid_path.join("io"))
+            .map(|s| convert_io(s.as_str()))
+            .ok(),
+        environ: if environ {
+            std::fs::read(pid_path.join("environ"))
+                .map(|bytes| convert_environ(&bytes))
+                .ok()
+        } else {
+            None
+        },
+    })
+}
+
+pub fn pid_detail(
+    proc: &ProcRoot,
+    accounts: &AccountFiles,
+    pid: u32,
+    environ: bool,
+) -> std::io::Result<ProcessDetail> {
+    process_detail(proc.path(), &accounts.passwd, pid, environ)
+}
+
+#[test]
+fn pid_detail_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let detail = process_detail(
+        &fixtures.join("proc"),
+        &fixtures.join("etc/passwd"),
+        812,
+        true,
+    )
+    .unwrap();
+    assert_eq!(detail.cwd.as_deref(), Some("/home/lqxc"));
+    assert_eq!(detail.exe.as_deref(), Some("/usr/bin/tmux"));
+    let fds = detail.fds.unwrap();
+    assert_eq!(
+        fds.iter().map(|f| f.fd).collect::<Vec<u32>>(),
+        [0, 1, 2, 3, 5]
+    );
+    assert_eq!(fds[3].target.as_deref(), Some("socket:[48213]"));
+    let open_files = detail
+        .limits
+        .iter()
+        .find(|l| l.name == "Max open files")
+        .unwrap();
+    assert_eq!(
+        (open_files.soft, open_files.hard),
+        (Some(1024), Some(524288))
+    );
+    assert_eq!(open_files.unit.as_deref(), Some("files"));
+    let nice = detail
+        .limits
+        .iter()
+        .find(|l| l.name == "Max nice priority")
+        .unwrap();
+    assert!(nice.unit.is_none());
+    assert_eq!(detail.io.unwrap().read_bytes, Some(1064960));
+    let environ = detail.environ.unwrap();
+    assert!(environ.contains(&("SHELL".to_string(), "/bin/zsh".to_string())));
+    assert!(environ.contains(&("NAME".to_string(), "caf\u{fffd}".to_string())));
+    assert!(environ
+        .iter()
+        .filter(|(name, _)| ["GITHUB_TOKEN", "PGPASSWORD", "MYSQL_PWD"].contains(&name.as_str()))
+        .all(|(_, value)| value == "[REDACTED]"));
+    assert!(environ.contains(&(
+        "DATABASE_URL".to_string(),
+        "postgres://[REDACTED]@localhost".to_string()
+    )));
+    assert_eq!(
+        mask_userinfo("redis://:hunter2@cache:6379/0,https://example.com/a@b"),
+        "redis://[REDACTED]@cache:6379/0,https://example.com/a@b"
+    );
+    let kthreadd = process_detail(
+        &fixtures.join("proc"),
+        &fixtures.join("etc/passwd"),
+        2,
+        false,
+    )
+    .unwrap();
+    assert!(kthreadd.environ.is_none());
+    let vanished = process_detail(
+        &fixtures.join("proc"),
+        &fixtures.join("etc/passwd"),
+        4242,
+        false,
+    );
+    assert_eq!(vanished.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+}
@@ -0,0 +1,194 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{cgroup::process_cgroup, cpu_stat::UnixTime, AccountFiles, BitType, ProcRoot};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ProcessInfo {
+    // (1) The process ID.
+    pid: u32,
+    // (4) The PID of the parent of this process.
+    ppid: Option<u32>,
+    /*
+       (3) One of the following characters, indicating
+       process state:
+           R  Running
+           S  Sleeping in an interruptible wait
+           D  Waiting in uninterruptible disk sleep
+           Z  Zombie
+           T  Stopped (on a signal)
+           t  Tracing stop
+           X  Dead
+           I  Idle (kernel thread)
+    */
+    pub(crate) state: Option<char>,
+    /*
+       (2) The filename of the executable, as shown in
+       stat between parentheses.  Truncated by the kernel
+       to 15 characters.
+    */
+    name: Option<String>,
+    // Real UID, the first column of `Uid` in status.
+    uid: Option<u32>,
+    // Login name of `uid`, resolved from `AccountFiles::passwd`.
+    user: Option<String>,
+    // Number of threads in this process (since Linux 2.6).
+    pub(crate) threads: Option<u32>,
+    // Resident set size, `VmRSS` in status.
+    rss: Option<BitType>,
+    // Virtual memory size, `VmSize` in status.
+    vsz: Option<BitType>,
+    /*
+       (22) The time the process started after system
+       boot, in clock ticks (sysconf(_SC_CLK_TCK)).
+    */
+    start_time: Option<UnixTime>,
+    /*
+       The complete command line for the process, empty
+       for kernel threads and zombies.
+    */
+    command_line: Vec<String>,
+    /*
+       Path of its cgroup v2, as in `Cgroup`, None on hosts
+       with cgroup v1 only.
+    */
+    cgroup: Option<String>,
+}
+
+// names and arguments are bytes, set by prctl(PR_SET_NAME) or execve(2)
+// to anything, so they are not required to be UTF-8
+#[inline]
+fn read_pid_file(proc_path: &Path, pid: u32, name: &str) -> std::io::Result<String> {
+    std::fs::read(proc_path.join(pid.to_string()).join(name))
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// comm may contain spaces and parentheses, so only trust the last ')'
+fn fill_stat(info: &mut ProcessInfo, stat: &str) {
+    let (head, tail) = match (stat.find('('), stat.rfind(')')) {
+        (Some(open), Some(close)) if open < close => (&stat[open + 1..close], &stat[close + 1..]),
+        _ => return,
+    };
+    info.name = Some(head.to_string());
+    // fields counted from (3) state
+    let fields = tail.split_whitespace().collect::<Vec<&str>>();
+    info.state = fields.first().and_then(|s| s.chars().next());
+    info.ppid = fields.get(1).and_then(|s| s.parse::<u32>().ok());
+    info.start_time = fields.get(19).and_then(|s| s.parse::<UnixTime>().ok());
+}
+
+fn fill_status(info: &mut ProcessInfo, status: &str) {
+    status
+        .split('\n')
+        .map(|s| s.split(':'))
+        .filter_map(|mut s| -> Option<(&str, &str)> { Some((s.next()?.trim(), s.next()?.trim())) })
+        .for_each(|(name, value)| match name {
+            "Uid" => {
+                info.uid = value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|s| s.parse::<u32>().ok())
+            }
+            "Threads" => info.threads = value.parse::<u32>().ok(),
+            "VmRSS" => info.rss = BitType::from_kernel(value).ok(),
+            "VmSize" => info.vsz = BitType::from_kernel(value).ok(),
+            _ => {}
+        });
+}
+
+#[inline]
+fn split_command_line(cmdline: &str) -> Vec<String> {
+    cmdline
+        .split('\0')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn users(passwd: &str) -> HashMap<u32, String> {
+    passwd
+        .split('\n')
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse::<u32>().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+// a missing passwd file only leaves `user` empty
+pub(super) fn read_users(passwd_path: &Path) -> HashMap<u32, String> {
+    users(
+        std::fs::read_to_string(passwd_path)
+            .unwrap_or_default()
+            .as_str(),
+    )
+}
+
+pub(super) fn process_info(
+    proc_path: &Path,
+    pid: u32,
+    users: &HashMap<u32, String>,
+) -> std::io::Result<ProcessInfo> {
+    let mut info = ProcessInfo {
+        pid,
+        ..ProcessInfo::default()
+    };
+    fill_stat(&mut info, read_pid_file(proc_path, pid, "stat")?.as_str());
+    fill_status(&mut info, read_pid_file(proc_path, pid, "status")?.as_str());
+    info.command_line = split_command_line(read_pid_file(proc_path, pid, "cmdline")?.as_str());
+    info.user = info.uid.and_then(|uid| users.get(&uid).cloned());
+    info.cgroup = read_pid_file(proc_path, pid, "cgroup")
+        .ok()
+        .and_then(|s| process_cgroup(s.as_str()));
+    Ok(info)
+}
+
+fn all_process_info(proc_path: &Path, passwd_path: &Path) -> std::io::Result<Vec<ProcessInfo>> {
+    let users = read_users(passwd_path);
+    let mut pids = std::fs::read_dir(proc_path)?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .collect::<Vec<u32>>();
+    pids.sort_unstable();
+    // a process may exit between read_dir and reading its files
+    Ok(pids
+        .into_iter()
+        .filter_map(|pid| process_info(proc_path, pid, &users).ok())
+        .collect())
+}
+
+pub fn pid_info(proc: &ProcRoot, accounts: &AccountFiles) -> std::io::Result<Vec<ProcessInfo>> {
+    all_process_info(proc.path(), &accounts.passwd)
+}
+
+#[test]
+fn pid_info_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let processes = all_process_info(&fixtures.join("proc"), &fixtures.join("etc/passwd")).unwrap();
+    assert_eq!(processes.len(), 3);
+    let tmux = processes.iter().find(|p| p.pid == 812).unwrap();
+    assert_eq!(tmux.name.as_deref(), Some("tmux: server"));
+    assert_eq!(tmux.ppid, Some(1));
+    assert_eq!(tmux.state, Some('S'));
+    assert_eq!(tmux.user.as_deref(), Some("lqxc"));
+    assert_eq!(tmux.start_time, Some(254311));
+    assert_eq!(
+        tmux.command_line.last().map(|s| s.as_str()),
+        Some("dash board")
+    );
+    assert_eq!(
+        tmux.cgroup.as_deref(),
+        Some("/user.slice/user-1000.slice/session-2.scope")
+    );
+    // an argument in latin-1 does not hide the process
+    let init = processes.iter().find(|p| p.pid == 1).unwrap();
+    assert_eq!(
+        init.command_line.last().map(|s| s.as_str()),
+        Some("\u{fffd}t\u{fffd}")
+    );
+    let kthreadd = processes.iter().find(|p| p.pid == 2).unwrap();
+    assert!(kthreadd.command_line.is_empty());
+    assert!(kthreadd.rss.is_none());
+}
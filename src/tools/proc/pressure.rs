@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    cgroup::unified_root,
+    parse::{number, ParseMode, Parsed, Parser, ProcParseError},
+    ProcRoot, SysRoot,
+};
+
+/// Share of wall time in which tasks were stalled on a resource.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct StallTime {
+    // percentages over the last 10, 60 and 300 seconds
+    pub(crate) avg10: f64,
+    pub(crate) avg60: f64,
+    pub(crate) avg300: f64,
+    // total stall time in microseconds
+    total: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ResourcePressure {
+    // at least one task stalled
+    pub(crate) some: Option<StallTime>,
+    /*
+       all non-idle tasks stalled at once.  Missing for cpu before
+       Linux 5.13, and always zero for cpu at the system level.
+    */
+    pub(crate) full: Option<StallTime>,
+}
+
+/// Pressure Stall Information of the whole system or of one cgroup.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Pressure {
+    pub(crate) cpu: Option<ResourcePressure>,
+    pub(crate) memory: Option<ResourcePressure>,
+    pub(crate) io: Option<ResourcePressure>,
+}
+
+// "avg10=6.79 avg60=5.75 avg300=2.63 total=130051502"
+fn stall_time(fields: std::str::SplitWhitespace) -> Result<StallTime, String> {
+    let mut stall = StallTime::default();
+    for field in fields {
+        let (name, value) = field
+            .split_once('=')
+            .ok_or_else(|| std::format!("{:?}: no '='", field))?;
+        match name {
+            "avg10" => stall.avg10 = number(value)?,
+            "avg60" => stall.avg60 = number(value)?,
+            "avg300" => stall.avg300 = number(value)?,
+            "total" => stall.total = number(value)?,
+            _ => return Err(std::format!("{:?}: unknown average", name)),
+        }
+    }
+    Ok(stall)
+}
+
+fn convert(source: &str, parser: &mut Parser) -> Result<ResourcePressure, ProcParseError> {
+    let mut pressure = ResourcePressure::default();
+    for (index, line) in source.split('\n').enumerate() {
+        let mut fields = line.split_whitespace();
+        let kind = match fields.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let slot = match kind {
+            "some" => &mut pressure.some,
+            "full" => &mut pressure.full,
+            _ => {
+                parser.problem(index + 1, kind, "unknown field".to_string())?;
+                continue;
+            }
+        };
+        match stall_time(fields) {
+            Ok(stall) => *slot = Some(stall),
+            Err(reason) => parser.problem(index + 1, kind, reason)?,
+        }
+    }
+    Ok(pressure)
+}
+
+// None on kernels built without PSI or booted with psi=0
+fn read_resource(
+    path: &Path,
+    mode: ParseMode,
+    warnings: &mut Vec<ProcParseError>,
+) -> std::io::Result<Option<ResourcePressure>> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut parser = Parser::new(path, mode);
+    let pressure = convert(source.as_str(), &mut parser)?;
+    warnings.extend(parser.finish(()).warnings);
+    Ok(Some(pressure))
+}
+
+// `name` turns a resource into its file, e.g. "cpu" into "cpu.pressure"
+fn read_pressure(
+    directory: &Path,
+    name: fn(&str) -> String,
+    mode: ParseMode,
+) -> std::io::Result<Parsed<Pressure>> {
+    let mut warnings = Vec::new();
+    let mut read =
+        |resource: &str| read_resource(&directory.join(name(resource)), mode, &mut warnings);
+    let pressure = Pressure {
+        cpu: read("cpu")?,
+        memory: read("memory")?,
+        io: read("io")?,
+    };
+    Ok(Parsed {
+        data: pressure,
+        warnings,
+    })
+}
+
+/// System wide pressure, from /proc/pressure.
+pub fn pressure(proc: &ProcRoot, mode: ParseMode) -> std::io::Result<Parsed<Pressure>> {
+    read_pressure(&proc.join("pressure"), |r| r.to_string(), mode)
+}
+
+/// Pressure of one cgroup v2, `cgroup` is its path below the hierarchy
+/// root, e.g. "system.slice/sshd.service".
+pub fn cgroup_pressure(
+    sys: &SysRoot,
+    cgroup: &str,
+    mode: ParseMode,
+) -> std::io::Result<Parsed<Pressure>> {
+    let directory = unified_root(sys)
+        .unwrap_or_else(|| sys.join("fs/cgroup"))
+        .join(cgroup.trim_start_matches('/'));
+    if !directory.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            std::format!("no cgroup {}", cgroup),
+        ));
+    }
+    read_pressure(&directory, |r| std::format!("{}.pressure", r), mode)
+}
+
+/// Pressure of the cgroups right below the root, e.g. "system.slice" and
+/// "user.slice".  Empty without a cgroup v2 hierarchy.
+pub fn top_cgroups_pressure(
+    sys: &SysRoot,
+    mode: ParseMode,
+) -> std::io::Result<Parsed<BTreeMap<String, Pressure>>> {
+    let mut cgroups = Parsed::<BTreeMap<String, Pressure>>::default();
+    let root = match unified_root(sys) {
+        Some(root) => root,
+        None => return Ok(cgroups),
+    };
+    for entry in std::fs::read_dir(&root)? {
+        let entry = entry?;
+        // controller files share the directory with the child cgroups
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let pressure = match cgroup_pressure(sys, name.as_str(), mode) {
+            // removed since read_dir, as a service that stopped
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            pressure => pressure?,
+        };
+        cgroups.warnings.extend(pressure.warnings);
+        cgroups.data.insert(name, pressure.data);
+    }
+    Ok(cgroups)
+}
+
+#[test]
+fn pressure_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let system = pressure(&ProcRoot::new(fixtures.join("proc")), ParseMode::Strict).unwrap();
+    let cpu = system.data.cpu.unwrap();
+    assert_eq!(
+        cpu.some,
+        Some(StallTime {
+            avg10: 6.79,
+            avg60: 5.75,
+            avg300: 2.63,
+            total: 130_051_502,
+        })
+    );
+    // before Linux 5.13
+    assert!(cpu.full.is_none());
+    let memory = system.data.memory.unwrap();
+    assert_eq!(memory.full.unwrap().total, 1_023_398);
+    // no io file in the fixture
+    assert!(system.data.io.is_none());
+    let sys = SysRoot::new(fixtures.join("sys"));
+    let cgroup = cgroup_pressure(&sys, "system.slice", ParseMode::Strict).unwrap();
+    assert_eq!(cgroup.data.io.unwrap().some.unwrap().avg60, 0.52);
+    assert!(cgroup_pressure(&sys, "missing.slice", ParseMode::Strict).is_err());
+    let cgroups = top_cgroups_pressure(&sys, ParseMode::Strict).unwrap().data;
+    assert_eq!(
+        cgroups.keys().map(String::as_str).collect::<Vec<_>>(),
+        ["system.slice", "user.slice"]
+    );
+    // no cpu.pressure in user.slice
+    assert!(cgroups["user.slice"].cpu.is_none());
+
+    let source = "some avg10=0.00 avg60=x avg300=0.00 total=0\nsome avg10=1.00 avg60=2.00 avg300=3.00 total=4\nboth avg10=0.00\n";
+    let mut parser = Parser::new(Path::new("/proc/pressure/io"), ParseMode::Lenient);
+    let pressure = convert(source, &mut parser).unwrap();
+    assert_eq!(pressure.some.unwrap().total, 4);
+    let warnings = parser.finish(()).warnings;
+    assert_eq!(
+        warnings
+            .iter()
+            .map(|w| (w.line, w.field.as_str()))
+            .collect::<Vec<_>>(),
+        [(1, "some"), (3, "both")]
+    );
+    let mut parser = Parser::new(Path::new("/proc/pressure/io"), ParseMode::Strict);
+    assert!(convert(source, &mut parser).is_err());
+}
@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+
+/// Where procfs is mounted.  `/proc` unless serverd watches something else,
+/// a tree captured from another machine or the host's /proc bind-mounted
+/// into a container.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcRoot(PathBuf);
+
+impl Default for ProcRoot {
+    fn default() -> Self {
+        ProcRoot(PathBuf::from("/proc"))
+    }
+}
+
+impl ProcRoot {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ProcRoot(path.into())
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.as_path()
+    }
+
+    /// Whether this is the procfs of serverd itself, what calls such as
+    /// statvfs(3) that go through its own mounts are only right for.
+    pub fn is_local(&self) -> bool {
+        self.0 == Path::new("/proc")
+    }
+
+    // e.g. `join("net/dev")`, no leading slash or it replaces the root
+    pub fn join(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.0.join(relative)
+    }
+
+    /// A file of the mount or network namespace of the watched machine,
+    /// e.g. `namespaced("net/dev")`.  In another procfs than the local one
+    /// self/ and net/ are those of serverd, so it is read from init.
+    pub fn namespaced(&self, relative: impl AsRef<Path>) -> PathBuf {
+        match self.is_local() {
+            true => self.0.join("self").join(relative),
+            false => self.0.join("1").join(relative),
+        }
+    }
+}
+
+/// Where sysfs is mounted, `/sys` by default, see `ProcRoot`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SysRoot(PathBuf);
+
+impl Default for SysRoot {
+    fn default() -> Self {
+        SysRoot(PathBuf::from("/sys"))
+    }
+}
+
+impl SysRoot {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SysRoot(path.into())
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.as_path()
+    }
+
+    pub fn join(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.0.join(relative)
+    }
+}
+
+/// The account files of the watched machine, /etc/passwd to name the
+/// owners of processes and /var/run/utmp for logged-in users.  They go
+/// along with `ProcRoot` when it is the host's.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountFiles {
+    pub passwd: PathBuf,
+    pub utmp: PathBuf,
+}
+
+impl Default for AccountFiles {
+    fn default() -> Self {
+        AccountFiles {
+            passwd: PathBuf::from("/etc/passwd"),
+            utmp: PathBuf::from("/var/run/utmp"),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    sysfs::{numbered, read_line},
+    SysRoot,
+};
+
+/// One channel of a chip, e.g. temp1.  Temperatures are in degrees
+/// Celsius, fans in RPM and voltages in volts, sysfs has milli-units.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Reading {
+    // e.g. "temp1"
+    channel: String,
+    // tempN_label, e.g. "Package id 0", missing on many chips
+    label: Option<String>,
+    value: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+    // the chip shuts down or throttles hard above it
+    critical: Option<f64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Chip {
+    // the N of hwmonN, only stable until the next boot
+    id: u32,
+    // the driver, e.g. "coretemp" or "nct6775"
+    name: Option<String>,
+    temperatures: Vec<Reading>,
+    fans: Vec<Reading>,
+    voltages: Vec<Reading>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ThermalZone {
+    // the N of thermal_zoneN
+    id: u32,
+    // e.g. "x86_pkg_temp" or "acpitz"
+    kind: Option<String>,
+    // in degrees Celsius
+    temperature: Option<f64>,
+    // the trip point of type critical, where the kernel powers off
+    critical: Option<f64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Sensors {
+    chips: Vec<Chip>,
+    thermal_zones: Vec<ThermalZone>,
+}
+
+// sysfs values are integers in milli-units, except RPM
+#[inline]
+fn read_scaled(path: &Path, scale: f64) -> Option<f64> {
+    Some(read_line(path)?.parse::<i64>().ok()? as f64 / scale)
+}
+
+// a chip or zone that went away while it was read has no channels
+#[inline]
+fn channels(directory: &Path, prefix: &str, suffix: &str) -> Vec<u32> {
+    numbered(directory, prefix, suffix).unwrap_or_default()
+}
+
+fn read_readings(directory: &Path, prefix: &str, scale: f64) -> Vec<Reading> {
+    channels(directory, prefix, "_input")
+        .into_iter()
+        .filter_map(|n| {
+            let channel = std::format!("{}{}", prefix, n);
+            let file = |name: &str| directory.join(std::format!("{}_{}", channel, name));
+            // a sensor that is not connected fails to read
+            let value = read_scaled(&file("input"), scale)?;
+            Some(Reading {
+                label: read_line(&file("label")),
+                value,
+                min: read_scaled(&file("min"), scale),
+                max: read_scaled(&file("max"), scale),
+                critical: read_scaled(&file("crit"), scale),
+                channel,
+            })
+        })
+        .collect()
+}
+
+fn read_chip(directory: &Path, id: u32) -> Chip {
+    // older drivers keep their attributes on the device, not the class
+    let directory = if directory.join("name").exists() {
+        directory.to_path_buf()
+    } else {
+        directory.join("device")
+    };
+    Chip {
+        id,
+        name: read_line(&directory.join("name")),
+        temperatures: read_readings(&directory, "temp", 1000.0),
+        fans: read_readings(&directory, "fan", 1.0),
+        voltages: read_readings(&directory, "in", 1000.0),
+    }
+}
+
+fn read_zone(directory: &Path, id: u32) -> ThermalZone {
+    let critical = channels(directory, "trip_point_", "_type")
+        .into_iter()
+        .find(|n| {
+            read_line(&directory.join(std::format!("trip_point_{}_type", n))).as_deref()
+                == Some("critical")
+        })
+        .and_then(|n| {
+            read_scaled(
+                &directory.join(std::format!("trip_point_{}_temp", n)),
+                1000.0,
+            )
+        });
+    ThermalZone {
+        id,
+        kind: read_line(&directory.join("type")),
+        temperature: read_scaled(&directory.join("temp"), 1000.0),
+        critical,
+    }
+}
+
+/// Every hwmon chip and thermal zone, either may be empty, virtual
+/// machines usually have neither.
+pub fn sensors(sys: &SysRoot) -> std::io::Result<Sensors> {
+    let hwmon = sys.join("class/hwmon");
+    let thermal = sys.join("class/thermal");
+    Ok(Sensors {
+        chips: channels(&hwmon, "hwmon", "")
+            .into_iter()
+            .map(|id| read_chip(&hwmon.join(std::format!("hwmon{}", id)), id))
+            .collect(),
+        thermal_zones: channels(&thermal, "thermal_zone", "")
+            .into_iter()
+            .map(|id| read_zone(&thermal.join(std::format!("thermal_zone{}", id)), id))
+            .collect(),
+    })
+}
+
+#[test]
+fn sensors_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let read = |root: &str| sensors(&SysRoot::new(fixtures.join(root))).unwrap();
+    let sensors = read("sys");
+    assert_eq!(sensors.chips.len(), 3);
+    let coretemp = &sensors.chips[0];
+    assert_eq!(coretemp.name.as_deref(), Some("coretemp"));
+    let package = &coretemp.temperatures[0];
+    assert_eq!(package.label.as_deref(), Some("Package id 0"));
+    assert_eq!(
+        (package.value, package.max, package.critical),
+        (47.0, Some(100.0), Some(100.0))
+    );
+    assert_eq!(coretemp.temperatures[1].channel, "temp2");
+    assert!(coretemp.fans.is_empty());
+    let superio = &sensors.chips[1];
+    assert_eq!(
+        superio
+            .fans
+            .iter()
+            .map(|f| (f.channel.as_str(), f.value, f.min))
+            .collect::<Vec<_>>(),
+        [("fan1", 1125.0, Some(300.0)), ("fan2", 0.0, None)]
+    );
+    // in1_input reads nothing, as disconnected inputs do
+    assert_eq!(superio.voltages.len(), 1);
+    assert_eq!(superio.voltages[0].label.as_deref(), Some("Vcore"));
+    assert_eq!(superio.voltages[0].value, 1.104);
+    assert!(superio.temperatures[0].label.is_none());
+    // attributes under device/
+    assert_eq!(sensors.chips[2].name.as_deref(), Some("acpitz"));
+    assert_eq!(sensors.chips[2].temperatures[0].value, 27.8);
+    let zones = &sensors.thermal_zones;
+    assert_eq!(
+        zones
+            .iter()
+            .map(|z| (z.kind.as_deref(), z.temperature, z.critical))
+            .collect::<Vec<_>>(),
+        [
+            (Some("acpitz"), Some(27.8), Some(105.0)),
+            (Some("x86_pkg_temp"), Some(47.0), None)
+        ]
+    );
+    let missing = read("missing");
+    assert!(missing.chips.is_empty() && missing.thermal_zones.is_empty());
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    parse::{ParseMode, Parsed, Parser, ProcParseError},
+    ProcRoot,
+};
+
+// __SO_ACCEPTCON in the Flags column of /proc/net/unix
+const UNIX_LISTENING: u32 = 1 << 16;
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SocketOwner {
+    pid: u32,
+    // /proc/[pid]/comm
+    name: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Socket {
+    // tcp, tcp6, udp, udp6 or unix
+    pub(crate) protocol: String,
+    /*
+       Addresses and ports of inet sockets.  0.0.0.0 and :: with
+       port 0 as the remote end mean not connected.
+    */
+    local_address: Option<IpAddr>,
+    pub(crate) local_port: Option<u16>,
+    remote_address: Option<IpAddr>,
+    remote_port: Option<u16>,
+    /*
+       As named by ss(8): LISTEN, ESTAB, TIME-WAIT, ... for tcp, UNCONN
+       or ESTAB for udp, and LISTEN, CONNECTED or UNCONN for unix.
+    */
+    pub(crate) state: Option<String>,
+    // owner of the socket, None for unix sockets
+    uid: Option<u32>,
+    inode: u64,
+    // bound path of unix sockets, '@' first for abstract ones
+    path: Option<String>,
+    /*
+       The processes holding the socket open, several after a fork.
+       Empty when their fd/ is not readable, for serverd sees the
+       fds of its own user only unless it runs as root.
+    */
+    owners: Vec<SocketOwner>,
+}
+
+fn tcp_state(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x01 => "ESTAB",
+        0x02 => "SYN-SENT",
+        0x03 => "SYN-RECV",
+        0x04 => "FIN-WAIT-1",
+        0x05 => "FIN-WAIT-2",
+        0x06 => "TIME-WAIT",
+        0x07 => "UNCONN",
+        0x08 => "CLOSE-WAIT",
+        0x09 => "LAST-ACK",
+        0x0A => "LISTEN",
+        0x0B => "CLOSING",
+        0x0C => "SYN-RECV",
+        _ => return None,
+    })
+}
+
+/*
+   "0100007F:1F90", the address is the in-memory __be32 words printed
+   as native integers, so their bytes come back with to_ne_bytes.
+*/
+fn endpoint(source: &str) -> Result<(IpAddr, u16), String> {
+    let (address, port) = source
+        .split_once(':')
+        .ok_or_else(|| std::format!("{:?}: no ':'", source))?;
+    let port = u16::from_str_radix(port, 16).map_err(|e| std::format!("{:?}: {}", port, e))?;
+    let mut bytes = Vec::with_capacity(16);
+    for at in (0..address.len()).step_by(8) {
+        let word = address
+            .get(at..at + 8)
+            .and_then(|word| u32::from_str_radix(word, 16).ok())
+            .ok_or_else(|| std::format!("{:?}: invalid address", address))?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let address = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(
+            TryInto::<[u8; 4]>::try_into(bytes.as_slice()).unwrap(),
+        )),
+        16 => IpAddr::V6(Ipv6Addr::from(
+            TryInto::<[u8; 16]>::try_into(bytes.as_slice()).unwrap(),
+        )),
+        _ => return Err(std::format!("{:?}: invalid address", address)),
+    };
+    Ok((address, port))
+}
+
+// "   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 41226 ..."
+fn inet_socket(protocol: &str, line: &str) -> Result<Socket, String> {
+    let fields = line.split_whitespace().collect::<Vec<&str>>();
+    if fields.len() < 10 {
+        return Err("too few columns".to_string());
+    }
+    let (local_address, local_port) = endpoint(fields[1])?;
+    let (remote_address, remote_port) = endpoint(fields[2])?;
+    let code = u8::from_str_radix(fields[3], 16).map_err(|e| e.to_string())?;
+    let state = if protocol.starts_with("udp") {
+        // udp only uses established and close
+        match code {
+            0x01 => Some("ESTAB"),
+            _ => Some("UNCONN"),
+        }
+    } else {
+        tcp_state(code)
+    };
+    Ok(Socket {
+        protocol: protocol.to_string(),
+        local_address: Some(local_address),
+        local_port: Some(local_port),
+        remote_address: Some(remote_address),
+        remote_port: Some(remote_port),
+        state: state.map(str::to_string),
+        uid: fields[7].parse::<u32>().ok(),
+        inode: fields[9].parse::<u64>().map_err(|e| e.to_string())?,
+        ..Socket::default()
+    })
+}
+
+// "0000000000000000: 00000002 00000000 00010000 0001 01 48213 /tmp/tmux-1000/default"
+fn unix_socket(line: &str) -> Result<Socket, String> {
+    // the inode is padded with spaces, and the path is the rest of the
+    // line after a single one, spaces in it included
+    let mut fields = Vec::with_capacity(7);
+    let mut rest = line;
+    while fields.len() < 7 {
+        let field = rest.trim_start();
+        let end = field.find(char::is_whitespace).unwrap_or(field.len());
+        if end == 0 {
+            return Err("too few columns".to_string());
+        }
+        fields.push(&field[..end]);
+        rest = &field[end..];
+    }
+    let flags = u32::from_str_radix(fields[3], 16).map_err(|e| e.to_string())?;
+    let state = if flags & UNIX_LISTENING != 0 {
+        "LISTEN"
+    } else if fields[5] == "03" {
+        "CONNECTED"
+    } else {
+        "UNCONN"
+    };
+    Ok(Socket {
+        protocol: "unix".to_string(),
+        state: Some(state.to_string()),
+        inode: fields[6].parse::<u64>().map_err(|e| e.to_string())?,
+        path: rest
+            .strip_prefix(' ')
+            .filter(|path| !path.is_empty())
+            .map(|path| path.to_string()),
+        ..Socket::default()
+    })
+}
+
+fn convert(
+    protocol: &str,
+    source: &str,
+    parser: &mut Parser,
+) -> Result<Vec<Socket>, ProcParseError> {
+    let mut sockets = Vec::new();
+    // the first line is a header
+    for (index, line) in source.split('\n').enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let socket = match protocol {
+            "unix" => unix_socket(line),
+            _ => inet_socket(protocol, line),
+        };
+        match socket {
+            Ok(socket) => sockets.push(socket),
+            Err(reason) => {
+                let field = line.split_whitespace().next().unwrap_or_default();
+                parser.problem(index + 1, field, reason)?
+            }
+        }
+    }
+    Ok(sockets)
+}
+
+// socket inode to the processes that have it open
+fn socket_owners(proc: &ProcRoot) -> std::io::Result<HashMap<u64, Vec<SocketOwner>>> {
+    let mut owners = HashMap::<u64, Vec<SocketOwner>>::new();
+    let mut pids = std::fs::read_dir(proc.path())?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .collect::<Vec<u32>>();
+    pids.sort_unstable();
+    for pid in pids {
+        let pid_path = proc.join(pid.to_string());
+        // gone or not ours
+        let fds = match std::fs::read_dir(pid_path.join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let mut inodes = fds
+            .filter_map(|fd| {
+                let target = std::fs::read_link(fd.ok()?.path()).ok()?;
+                target
+                    .to_str()?
+                    .strip_prefix("socket:[")?
+                    .strip_suffix(']')?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .collect::<Vec<u64>>();
+        inodes.sort_unstable();
+        inodes.dedup();
+        if inodes.is_empty() {
+            continue;
+        }
+        let name = std::fs::read_to_string(pid_path.join("comm"))
+            .ok()
+            .map(|s| s.trim_end().to_string());
+        for inode in inodes {
+            owners.entry(inode).or_default().push(SocketOwner {
+                pid,
+                name: name.clone(),
+            });
+        }
+    }
+    Ok(owners)
+}
+
+/// Every tcp, udp and unix socket of the network namespace of serverd,
+/// with the processes owning them.
+pub fn sockets(proc: &ProcRoot, mode: ParseMode) -> std::io::Result<Parsed<Vec<Socket>>> {
+    let mut sockets = Vec::new();
+    let mut warnings = Vec::new();
+    for protocol in ["tcp", "tcp6", "udp", "udp6", "unix"].iter() {
+        let path = proc.namespaced("net").join(protocol);
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            // no ipv6 on this kernel
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let mut parser = Parser::new(&path, mode);
+        sockets.extend(convert(protocol, source.as_str(), &mut parser)?);
+        warnings.extend(parser.finish(()).warnings);
+    }
+    let mut owners = socket_owners(proc)?;
+    for socket in sockets.iter_mut() {
+        // inode 0 for sockets in TIME-WAIT, no one holds them
+        if socket.inode != 0 {
+            socket.owners = owners.remove(&socket.inode).unwrap_or_default();
+        }
+    }
+    Ok(Parsed {
+        data: sockets,
+        warnings,
+    })
+}
+
+#[test]
+fn sockets_test() {
+    // the addresses of the fixture are in little endian, as on x86
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let parsed = sockets(&ProcRoot::new(fixtures.join("proc")), ParseMode::Strict).unwrap();
+    let sockets = parsed.data;
+    let http = sockets
+        .iter()
+        .find(|s| s.local_port == Some(8080) && s.state.as_deref() == Some("LISTEN"))
+        .unwrap();
+    assert_eq!(http.protocol, "tcp");
+    assert_eq!(
+        http.local_address,
+        Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)))
+    );
+    // a socket unit of systemd
+    assert_eq!(
+        http.owners,
+        [SocketOwner {
+            pid: 1,
+            name: Some("systemd".to_string())
+        }]
+    );
+    let ssh = sockets
+        .iter()
+        .find(|s| s.protocol == "tcp6" && s.local_port == Some(22))
+        .unwrap();
+    assert_eq!(ssh.local_address, Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)));
+    assert_eq!(ssh.owners[0].pid, 1);
+    let established = sockets
+        .iter()
+        .find(|s| s.state.as_deref() == Some("ESTAB"))
+        .unwrap();
+    assert_eq!(
+        (established.remote_address, established.remote_port),
+        (
+            Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))),
+            Some(51_844)
+        )
+    );
+    assert!(established.owners.is_empty());
+    let v6 = sockets.iter().find(|s| s.protocol == "udp6").unwrap();
+    assert_eq!(v6.local_address, Some("fe80::1".parse().unwrap()));
+    assert_eq!(v6.state.as_deref(), Some("UNCONN"));
+    let tmux = sockets.iter().find(|s| s.protocol == "unix").unwrap();
+    assert_eq!(
+        (tmux.path.as_deref(), tmux.state.as_deref()),
+        (Some("/tmp/tmux-1000/default"), Some("LISTEN"))
+    );
+    assert_eq!(tmux.owners[0].pid, 812);
+    let spaced =
+        unix_socket("0000000000000000: 00000002 00000000 00010000 0001 01  4821 /run/my app.sock")
+            .unwrap();
+    assert_eq!(
+        (spaced.inode, spaced.path.as_deref()),
+        (4821, Some("/run/my app.sock"))
+    );
+    assert!(endpoint("0100007F").is_err());
+}
@@ -0,0 +1,69 @@
+use std::{path::Path, str::FromStr};
+
+use crate::tools::BitType;
+
+/// A file of a single value, trimmed.  None when it cannot be read, what
+/// sysfs answers for a sensor that is not connected, among others.
+pub(crate) fn read_line(path: &Path) -> Option<String> {
+    Some(std::fs::read_to_string(path).ok()?.trim().to_string())
+}
+
+// e.g. memory.current, None for "max" as well
+pub(crate) fn read_value<T: FromStr>(path: &Path) -> Option<T> {
+    read_line(path)?.parse::<T>().ok()
+}
+
+// e.g. memory.current in bytes, None for "max" as well
+pub(crate) fn read_size(path: &Path) -> Option<BitType> {
+    BitType::from_kernel(read_line(path)?.as_str()).ok()
+}
+
+// e.g. scaling_available_governors, "performance powersave"
+pub(crate) fn read_words(path: &Path) -> Vec<String> {
+    read_line(path)
+        .map(|s| s.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// The N of every entry of `directory` named like {prefix}N{suffix},
+/// sorted, e.g. the 0 and 2 of cpufreq/policy0 and cpufreq/policy2.
+pub(crate) fn numbered(directory: &Path, prefix: &str, suffix: &str) -> std::io::Result<Vec<u32>> {
+    let mut numbers = std::fs::read_dir(directory)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?
+                .strip_prefix(prefix)?
+                .strip_suffix(suffix)?
+                .parse::<u32>()
+                .ok()
+        })
+        .collect::<Vec<u32>>();
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+#[test]
+fn sysfs_test() {
+    let sys = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sys");
+    let cpu = sys.join("devices/system/cpu");
+    assert_eq!(numbered(&cpu, "cpu", "").unwrap(), [0, 1, 2, 3, 4]);
+    let hwmon = sys.join("class/hwmon/hwmon0");
+    assert_eq!(numbered(&hwmon, "temp", "_input").unwrap(), [1, 2]);
+    assert!(numbered(&sys.join("missing"), "cpu", "").is_err());
+    let policy = cpu.join("cpufreq/policy0");
+    assert_eq!(
+        read_value::<u64>(&policy.join("cpuinfo_max_freq")),
+        Some(4_200_000)
+    );
+    assert_eq!(
+        read_words(&policy.join("scaling_available_governors")),
+        ["performance", "powersave"]
+    );
+    assert!(read_line(&policy.join("missing")).is_none());
+    let slice = sys.join("fs/cgroup/system.slice");
+    assert_eq!(
+        read_size(&slice.join("memory.current")),
+        Some(BitType::from_bytes(1_238_056_960))
+    );
+    assert!(read_size(&slice.join("memory.max")).is_none());
+}
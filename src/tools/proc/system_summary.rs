@@ -0,0 +1,133 @@
+use std::{convert::TryInto, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{load_avg::LoadAvg, sysfs::read_line, AccountFiles, ProcRoot};
+
+// struct utmp of glibc on 64-bit Linux, see utmp(5)
+const UTMP_SIZE: usize = 384;
+const USER_PROCESS: i16 = 7;
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct LoggedUser {
+    // ut_user, the login name
+    user: String,
+    // ut_line, device name of the tty without "/dev/"
+    tty: String,
+    // ut_host, the remote host or X display, empty for local logins
+    host: String,
+    // ut_pid of the login process
+    pid: i32,
+    // ut_tv, unix time in milliseconds
+    login_time: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SystemSummary {
+    hostname: Option<String>,
+    // /proc/sys/kernel/osrelease, e.g. "5.15.0-91-generic"
+    kernel: Option<String>,
+    /*
+       The uptime of the system (including time spent in
+       suspend) and the amount of time spent in the idle
+       process, both in seconds.
+    */
+    uptime: Option<f64>,
+    idle: Option<f64>,
+    load_avg: Option<LoadAvg>,
+    users: Vec<LoggedUser>,
+}
+
+// fixed size fields are NUL padded, but not NUL terminated when full
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+fn convert_record(record: &[u8]) -> Option<LoggedUser> {
+    let i16_at = |at: usize| i16::from_ne_bytes(record[at..at + 2].try_into().unwrap());
+    let i32_at = |at: usize| i32::from_ne_bytes(record[at..at + 4].try_into().unwrap());
+    if i16_at(0) != USER_PROCESS {
+        return None;
+    }
+    let user = c_string(&record[44..76]);
+    if user.is_empty() {
+        return None;
+    }
+    Some(LoggedUser {
+        user,
+        tty: c_string(&record[8..40]),
+        host: c_string(&record[76..332]),
+        pid: i32_at(4),
+        login_time: i32_at(340) as i64 * 1000 + i32_at(344) as i64 / 1000,
+    })
+}
+
+fn read_users(utmp: &Path) -> std::io::Result<Vec<LoggedUser>> {
+    match std::fs::read(utmp) {
+        Ok(records) => Ok(records
+            .chunks_exact(UTMP_SIZE)
+            .filter_map(convert_record)
+            .collect()),
+        // containers and minimal systems run without login accounting
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_system_summary(
+    proc: &ProcRoot,
+    utmp: &Path,
+    load_avg: Option<LoadAvg>,
+) -> std::io::Result<SystemSummary> {
+    let uptime = std::fs::read_to_string(proc.join("uptime"))?;
+    let mut uptime = uptime.split_whitespace().map(|s| s.parse::<f64>().ok());
+    Ok(SystemSummary {
+        hostname: read_line(&proc.join("sys/kernel/hostname")),
+        kernel: read_line(&proc.join("sys/kernel/osrelease")),
+        uptime: uptime.next().flatten(),
+        idle: uptime.next().flatten(),
+        load_avg,
+        users: read_users(utmp)?,
+    })
+}
+
+/// Header of the dashboard, `load_avg` is the reading of the same sample.
+pub fn system_summary(
+    proc: &ProcRoot,
+    accounts: &AccountFiles,
+    load_avg: Option<LoadAvg>,
+) -> std::io::Result<SystemSummary> {
+    read_system_summary(proc, &accounts.utmp, load_avg)
+}
+
+#[test]
+fn system_summary_test() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let summary = read_system_summary(
+        &ProcRoot::new(fixtures.join("proc")),
+        &fixtures.join("var/run/utmp"),
+        None,
+    )
+    .unwrap();
+    assert_eq!(summary.hostname.as_deref(), Some("dash-board"));
+    assert_eq!(summary.kernel.as_deref(), Some("5.15.0-91-generic"));
+    assert_eq!(
+        (summary.uptime, summary.idle),
+        (Some(350_735.47), Some(234_388.9))
+    );
+    // reboot, runlevel, getty and dead process records are not logins
+    assert_eq!(
+        summary
+            .users
+            .iter()
+            .map(|u| (u.user.as_str(), u.tty.as_str(), u.host.as_str()))
+            .collect::<Vec<_>>(),
+        [("alice", "tty2", ""), ("bob", "pts/0", "192.168.1.20")]
+    );
+    assert_eq!(summary.users[1].pid, 2310);
+    assert_eq!(summary.users[1].login_time, 1_697_003_600_250);
+    assert!(read_users(&fixtures.join("var/run/missing"))
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{
+    parse::{number, ParseMode, Parsed, Parser, ProcParseError},
+    ProcRoot,
+};
+
+/// The counters of /proc/vmstat worth watching, since boot.  It has over
+/// a hundred more that come and go between kernel versions, those are
+/// not read.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct VmStat {
+    // KiB paged in from and out to block devices
+    page_in: Option<u64>,
+    page_out: Option<u64>,
+    // pages swapped in and out
+    swap_in: Option<u64>,
+    swap_out: Option<u64>,
+    // page faults, and the major ones among them, which needed I/O
+    page_faults: Option<u64>,
+    major_faults: Option<u64>,
+    // processes killed by the OOM killer (since Linux 4.13)
+    oom_kills: Option<u64>,
+}
+
+/// Per second, except `oom_kills`, the kills since the previous reading.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct VmRate {
+    // in bytes
+    pub(crate) page_in: f64,
+    pub(crate) page_out: f64,
+    // in pages
+    pub(crate) swap_in: f64,
+    pub(crate) swap_out: f64,
+    pub(crate) page_faults: f64,
+    pub(crate) major_faults: f64,
+    oom_kills: u64,
+}
+
+fn convert(source: &str, parser: &mut Parser) -> Result<VmStat, ProcParseError> {
+    let mut vm_stat = VmStat::default();
+    for (index, line) in source.split('\n').enumerate() {
+        let mut fields = line.split_whitespace();
+        let (name, value) = match (fields.next(), fields.next()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => continue,
+        };
+        let slot = match name {
+            "pgpgin" => &mut vm_stat.page_in,
+            "pgpgout" => &mut vm_stat.page_out,
+            "pswpin" => &mut vm_stat.swap_in,
+            "pswpout" => &mut vm_stat.swap_out,
+            "pgfault" => &mut vm_stat.page_faults,
+            "pgmajfault" => &mut vm_stat.major_faults,
+            "oom_kill" => &mut vm_stat.oom_kills,
+            _ => continue,
+        };
+        match number::<u64>(value) {
+            Ok(value) => *slot = Some(value),
+            Err(reason) => parser.problem(index + 1, name, reason)?,
+        }
+    }
+    Ok(vm_stat)
+}
+
+pub fn vm_stat(proc: &ProcRoot, mode: ParseMode) -> std::io::Result<Parsed<VmStat>> {
+    let path = proc.join("vmstat");
+    let mut parser = Parser::new(&path, mode);
+    let vm_stat = convert(std::fs::read_to_string(&path)?.as_str(), &mut parser)?;
+    Ok(parser.finish(vm_stat))
+}
+
+#[inline]
+fn delta(previous: Option<u64>, current: Option<u64>) -> u64 {
+    current.unwrap_or(0).saturating_sub(previous.unwrap_or(0))
+}
+
+/// Rates between two `vm_stat()` readings taken `seconds` apart.
+pub fn vm_rate(previous: &VmStat, current: &VmStat, seconds: f64) -> Option<VmRate> {
+    if seconds <= 0.0 {
+        return None;
+    }
+    let rate = |p: Option<u64>, c: Option<u64>| delta(p, c) as f64 / seconds;
+    Some(VmRate {
+        page_in: rate(previous.page_in, current.page_in) * 1024.0,
+        page_out: rate(previous.page_out, current.page_out) * 1024.0,
+        swap_in: rate(previous.swap_in, current.swap_in),
+        swap_out: rate(previous.swap_out, current.swap_out),
+        page_faults: rate(previous.page_faults, current.page_faults),
+        major_faults: rate(previous.major_faults, current.major_faults),
+        oom_kills: delta(previous.oom_kills, current.oom_kills),
+    })
+}
+
+#[test]
+fn vm_stat_test() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let parsed = vm_stat(&ProcRoot::new(fixtures.join("proc")), ParseMode::Strict).unwrap();
+    let vm = parsed.data;
+    assert_eq!((vm.swap_in, vm.swap_out), (Some(1204), Some(9873)));
+    assert_eq!(vm.major_faults, Some(30422));
+    assert_eq!(vm.oom_kills, Some(2));
+    // the other counters are no warnings
+    assert!(parsed.warnings.is_empty());
+    let later = VmStat {
+        page_in: vm.page_in.map(|v| v + 2048),
+        page_faults: vm.page_faults.map(|v| v + 10_000),
+        oom_kills: vm.oom_kills.map(|v| v + 1),
+        ..vm.clone()
+    };
+    let rate = vm_rate(&vm, &later, 2.0).unwrap();
+    assert_eq!(rate.page_in, 1024.0 * 1024.0);
+    assert_eq!((rate.page_faults, rate.swap_out), (5000.0, 0.0));
+    assert_eq!(rate.oom_kills, 1);
+    let mut parser = Parser::new(std::path::Path::new("/proc/vmstat"), ParseMode::Strict);
+    let error = convert("pgfault many\n", &mut parser).unwrap_err();
+    assert_eq!(error.field, "pgfault");
+}
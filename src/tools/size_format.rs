@@ -0,0 +1,382 @@
+use serde::{ser, Serialize, Serializer};
+
+use crate::tools::{BitType, SizeFormat};
+
+// the newtype name `BitType` serializes under, what `SizeSerializer` looks for
+pub(crate) const BIT_TYPE: &str = "BitType";
+
+/// Serializes `value` with every `BitType` in it written as `format`, e.g.
+/// `serde_json::to_string(&with_size_format(&memory, SizeFormat::Iec))`.
+/// Everything else is written as it would be without it.
+pub fn with_size_format<T: ?Sized + Serialize>(
+    value: &T,
+    format: SizeFormat,
+) -> WithSizeFormat<'_, T> {
+    WithSizeFormat { value, format }
+}
+
+pub struct WithSizeFormat<'a, T: ?Sized> {
+    value: &'a T,
+    format: SizeFormat,
+}
+
+impl<T: ?Sized + Serialize> Serialize for WithSizeFormat<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(SizeSerializer {
+            inner: serializer,
+            format: self.format,
+            size: false,
+        })
+    }
+}
+
+/*
+   Passes everything on to `inner`, the parts of compound values wrapped
+   again so the format reaches them.  `size` is set for the u64 inside a
+   `BitType`, the only number it writes differently.
+*/
+struct SizeSerializer<S> {
+    inner: S,
+    format: SizeFormat,
+    size: bool,
+}
+
+impl<S> SizeSerializer<S> {
+    fn compound(inner: S, format: SizeFormat) -> Self {
+        SizeSerializer {
+            inner,
+            format,
+            size: false,
+        }
+    }
+}
+
+macro_rules! forward {
+    ($($method:ident($type:ty)),* $(,)?) => {
+        $(
+            fn $method(self, v: $type) -> Result<S::Ok, S::Error> {
+                self.inner.$method(v)
+            }
+        )*
+    };
+}
+
+impl<S: Serializer> Serializer for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = SizeSerializer<S::SerializeSeq>;
+    type SerializeTuple = SizeSerializer<S::SerializeTuple>;
+    type SerializeTupleStruct = SizeSerializer<S::SerializeTupleStruct>;
+    type SerializeTupleVariant = SizeSerializer<S::SerializeTupleVariant>;
+    type SerializeMap = SizeSerializer<S::SerializeMap>;
+    type SerializeStruct = SizeSerializer<S::SerializeStruct>;
+    type SerializeStructVariant = SizeSerializer<S::SerializeStructVariant>;
+
+    forward!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str),
+    );
+
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        match (self.size, self.format) {
+            (true, SizeFormat::Iec) | (true, SizeFormat::Si) => self
+                .inner
+                .serialize_str(BitType::from_bytes(v).format(self.format).as_str()),
+            _ => self.inner.serialize_u64(v),
+        }
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_some(&with_size_format(value, self.format))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        if name == BIT_TYPE {
+            return value.serialize(SizeSerializer {
+                inner: self.inner,
+                format: self.format,
+                size: true,
+            });
+        }
+        self.inner
+            .serialize_newtype_struct(name, &with_size_format(value, self.format))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_newtype_variant(
+            name,
+            index,
+            variant,
+            &with_size_format(value, self.format),
+        )
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let seq = self.inner.serialize_seq(len)?;
+        Ok(SizeSerializer::compound(seq, self.format))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let tuple = self.inner.serialize_tuple(len)?;
+        Ok(SizeSerializer::compound(tuple, self.format))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let tuple = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(SizeSerializer::compound(tuple, self.format))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let tuple = self
+            .inner
+            .serialize_tuple_variant(name, index, variant, len)?;
+        Ok(SizeSerializer::compound(tuple, self.format))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let map = self.inner.serialize_map(len)?;
+        Ok(SizeSerializer::compound(map, self.format))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let structure = self.inner.serialize_struct(name, len)?;
+        Ok(SizeSerializer::compound(structure, self.format))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let structure = self
+            .inner
+            .serialize_struct_variant(name, index, variant, len)?;
+        Ok(SizeSerializer::compound(structure, self.format))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<S: ser::SerializeSeq> ser::SerializeSeq for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner
+            .serialize_element(&with_size_format(value, self.format))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: ser::SerializeTuple> ser::SerializeTuple for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner
+            .serialize_element(&with_size_format(value, self.format))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: ser::SerializeTupleStruct> ser::SerializeTupleStruct for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner
+            .serialize_field(&with_size_format(value, self.format))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: ser::SerializeTupleVariant> ser::SerializeTupleVariant for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner
+            .serialize_field(&with_size_format(value, self.format))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: ser::SerializeMap> ser::SerializeMap for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), S::Error> {
+        self.inner
+            .serialize_key(&with_size_format(key, self.format))
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner
+            .serialize_value(&with_size_format(value, self.format))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: ser::SerializeStruct> ser::SerializeStruct for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        self.inner
+            .serialize_field(key, &with_size_format(value, self.format))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: ser::SerializeStructVariant> ser::SerializeStructVariant for SizeSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        self.inner
+            .serialize_field(key, &with_size_format(value, self.format))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+#[test]
+fn size_format_test() {
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Disk {
+        name: &'static str,
+        size: BitType,
+        free: Option<BitType>,
+        blocks: u64,
+        parts: Vec<(u32, BitType)>,
+    }
+    let disk = Disk {
+        name: "sda",
+        size: BitType::from_bytes(2 << 20),
+        free: Some(BitType::from_bytes(2_000_000)),
+        blocks: 4096,
+        parts: vec![(1, BitType::from_bytes(512))],
+    };
+    let iec = serde_json::to_value(with_size_format(&disk, SizeFormat::Iec)).unwrap();
+    assert_eq!(
+        iec,
+        serde_json::json!({
+            "name": "sda",
+            "size": "2.0 MiB",
+            "free": "1.9 MiB",
+            // only the sizes
+            "blocks": 4096,
+            "parts": [[1, "512 B"]],
+        })
+    );
+    let si = serde_json::to_string(&with_size_format(&disk.free, SizeFormat::Si)).unwrap();
+    assert_eq!(si, r#""2.0 MB""#);
+    // without it, and with Bytes, sizes are numbers
+    assert_eq!(serde_json::to_value(&disk).unwrap()["size"], 2 << 20);
+    let bytes = serde_json::to_value(with_size_format(&disk, SizeFormat::Bytes)).unwrap();
+    assert_eq!(bytes, serde_json::to_value(&disk).unwrap());
+    let mut by_name = BTreeMap::new();
+    by_name.insert("sda", disk.size);
+    assert_eq!(
+        serde_json::to_string(&with_size_format(&by_name, SizeFormat::Iec)).unwrap(),
+        r#"{"sda":"2.0 MiB"}"#
+    );
+}